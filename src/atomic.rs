@@ -0,0 +1,62 @@
+// Crash safe file replacement: write a temp file, fsync it and rename it over
+// the target, keeping the previous contents as a .bak
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::os::unix::fs::{MetadataExt, chown};
+use std::path::Path;
+
+pub fn temp_path(path: &str) -> String {
+    format!("{}.tmp", path)
+}
+
+pub fn backup_path(path: &str) -> String {
+    format!("{}.bak", path)
+}
+
+// Keep the last copy as .bak, unless it is empty and so not worth keeping
+fn rotate_backup(path: &str) -> io::Result<()> {
+    match fs::metadata(path) {
+        Ok(metadata) if metadata.len() > 0 => fs::copy(path, backup_path(path)).map(|_| ()),
+        Ok(_) => Ok(()),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(e),
+    }
+}
+
+fn sync_parent(path: &Path) -> io::Result<()> {
+    match path.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+        Some(parent) => File::open(parent)?.sync_all(),
+        None => File::open(".")?.sync_all(),
+    }
+}
+
+pub fn write(path: &str, content: &str, backup: bool) -> io::Result<()> {
+    let temp = temp_path(path);
+    let result = (|| {
+        let mut file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&temp)?;
+        file.write_all(content.as_bytes())?;
+        file.sync_all()?;
+
+        // The replacement keeps the owner and mode of the file it replaces,
+        // package files stay the user's when written as root
+        if let Ok(metadata) = fs::metadata(path) {
+            fs::set_permissions(&temp, metadata.permissions())?;
+            let _ = chown(&temp, Some(metadata.uid()), Some(metadata.gid()));
+        }
+
+        if backup {
+            rotate_backup(path)?;
+        }
+        fs::rename(&temp, path)?;
+        sync_parent(Path::new(path))
+    })();
+
+    if result.is_err() {
+        let _ = fs::remove_file(&temp);
+    }
+    result
+}
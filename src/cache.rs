@@ -0,0 +1,77 @@
+// Locating exact package versions in the pacman cache or on an archive mirror
+use crate::database::PACMAN_CONF;
+use crate::pacman_conf::PacmanConf;
+use crate::paths;
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+
+fn file_prefix(name: &str, version: &str, arch: &str) -> String {
+    format!("{}-{}-{}.pkg.tar.", name, version, arch)
+}
+
+fn find_in(directory: &str, name: &str, version: &str, arch: &str) -> Option<PathBuf> {
+    let prefix = file_prefix(name, version, arch);
+    fs::read_dir(directory)
+        .ok()?
+        .flatten()
+        .map(|entry| entry.path())
+        .find(|path| {
+            path.file_name()
+                .and_then(|name| name.to_str())
+                .is_some_and(|name| name.starts_with(&prefix) && !name.ends_with(".sig"))
+        })
+}
+
+pub fn find_cached(name: &str, version: &str, arch: &str) -> Option<PathBuf> {
+    find_in(&paths::cachedir(), name, version, arch)
+}
+
+// Directories of the file:// repositories in pacman.conf, such as the local repository
+pub fn local_repository_directories() -> Vec<String> {
+    let Ok(conf) = PacmanConf::read(PACMAN_CONF) else {
+        return Vec::new();
+    };
+    conf.repositories()
+        .iter()
+        .filter_map(|name| conf.section(name).map(|section| (name, section.options())))
+        .flat_map(|(name, options)| {
+            options
+                .into_iter()
+                .filter(|(key, _)| key == "Server")
+                .filter_map(|(_, value)| value?.strip_prefix("file://").map(str::to_string))
+                .map(|directory| directory.replace("$repo", name).replace("$arch", env::consts::ARCH))
+                .collect::<Vec<_>>()
+        })
+        .collect()
+}
+
+// Available without network: in the package cache or a file:// repository
+pub fn find_local(name: &str, version: &str, arch: &str) -> Option<PathBuf> {
+    find_cached(name, version, arch).or_else(|| {
+        local_repository_directories()
+            .iter()
+            .find_map(|directory| find_in(directory, name, version, arch))
+    })
+}
+
+// Layout used by the Arch Linux Archive: packages/<first letter>/<name>/<file>
+pub fn archive_url(mirror: &str, name: &str, version: &str, arch: &str) -> String {
+    let initial = name.chars().next().unwrap_or('_');
+    format!(
+        "{}/packages/{}/{}/{}zst",
+        mirror.trim_end_matches('/'),
+        initial,
+        name,
+        file_prefix(name, version, arch)
+    )
+}
+
+// Something `pacman -U` can install: the cached file if present, otherwise
+// the archive URL
+pub fn package_source(mirror: &str, name: &str, version: &str, arch: &str) -> String {
+    match find_cached(name, version, arch) {
+        Some(path) => path.display().to_string(),
+        None => archive_url(mirror, name, version, arch),
+    }
+}
@@ -0,0 +1,76 @@
+// Opening the pacman databases of the target root through alpm
+use crate::pacman_conf::PacmanConf;
+use crate::paths;
+use alpm::{Alpm, Db, Package, SigLevel};
+
+// Like pacstrap, the host configuration drives installs into another root
+pub const PACMAN_CONF: &str = "/etc/pacman.conf";
+
+// Names of the repositories enabled in pacman.conf, in priority order
+pub fn configured_repositories() -> Vec<String> {
+    PacmanConf::read(PACMAN_CONF)
+        .map(|conf| conf.repositories())
+        .unwrap_or_default()
+}
+
+pub fn open() -> Alpm {
+    Alpm::new(paths::root(), paths::dbpath().as_str()).expect("Failed to read database")
+}
+
+// Names of the packages installed on the host, which also carries the keyring
+// and mirrorlist packages of the repositories in its pacman.conf
+pub fn host_packages() -> Vec<String> {
+    Alpm::new("/", paths::PACMAN_DBPATH)
+        .map(|alpm| alpm.localdb().pkgs().iter().map(|pkg| pkg.name().to_string()).collect())
+        .unwrap_or_default()
+}
+
+// Declarations may name a group such as base-devel or a virtual package,
+// neither is a package of the local database
+pub fn satisfied(localdb: &Db, target: &str) -> bool {
+    localdb.pkg(target).is_ok()
+        || localdb
+            .group(target)
+            .is_ok_and(|group| !group.packages().is_empty())
+        || localdb.pkgs().find_satisfier(target).is_some()
+}
+
+pub fn is_installed(target: &str) -> bool {
+    satisfied(open().localdb(), target)
+}
+
+pub fn open_with_syncdbs() -> Alpm {
+    let alpm = open();
+    for repository in configured_repositories() {
+        let _ = alpm.register_syncdb(repository, SigLevel::USE_DEFAULT);
+    }
+    alpm
+}
+
+fn ships(db: &Db, pkg: &Package) -> bool {
+    db.pkg(pkg.name()).is_ok_and(|candidate| {
+        candidate.version() == pkg.version() && candidate.build_date() == pkg.build_date()
+    })
+}
+
+// Whether the installed build is known to come from another repository than
+// the pinned one, None when that repository is not configured or lacks the
+// package. An older build of the pinned repository is left to the system
+// upgrade, reinstalling it alone would be a partial upgrade. `aur` means no
+// sync repository ships it
+pub fn pinned_elsewhere(alpm: &Alpm, pkg: &Package, repo: &str) -> Option<bool> {
+    if repo == "aur" {
+        return Some(repository_of(alpm, pkg).is_some());
+    }
+    let pinned = alpm.syncdbs().iter().find(|db| db.name() == repo)?;
+    pinned.pkg(pkg.name()).ok()?;
+    Some(!ships(pinned, pkg) && repository_of(alpm, pkg).is_some())
+}
+
+// Sync repository that ships exactly this build of an installed package
+pub fn repository_of(alpm: &Alpm, pkg: &Package) -> Option<String> {
+    alpm.syncdbs()
+        .iter()
+        .find(|db| ships(db, pkg))
+        .map(|db| db.name().to_string())
+}
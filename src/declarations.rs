@@ -0,0 +1,276 @@
+// Package declarations read from the yaml files of the packages folder
+use crate::hooks::Hook;
+use crate::keyring::Key;
+use crate::local_repository::LocalRepository;
+use crate::mirrors::MirrorStrategy;
+use crate::pacman_conf::OptionValue;
+use crate::repositories::Repository;
+use crate::{YELLOW_WARNING, write_privileged_file};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::BufReader;
+use std::path::Path;
+
+#[derive(Debug, Clone)]
+pub struct Declaration {
+    pub name: String,
+    // File name inside the packages folder that declares the package
+    pub file: String,
+    pub repo: Option<String>,
+}
+
+// A package name, or a mapping that also pins the repository it comes from,
+// e.g. `{name: firefox, repo: extra}`
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(untagged)]
+pub enum PackageEntry {
+    Name(String),
+    Pinned { name: String, repo: String },
+}
+
+impl PackageEntry {
+    pub fn name(&self) -> &str {
+        match self {
+            PackageEntry::Name(name) => name,
+            PackageEntry::Pinned { name, .. } => name,
+        }
+    }
+
+    pub fn repo(&self) -> Option<&str> {
+        match self {
+            PackageEntry::Name(_) => None,
+            PackageEntry::Pinned { repo, .. } => Some(repo),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct Sections {
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub packages: Vec<PackageEntry>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub repositories: Vec<Repository>,
+    // Global options of pacman.conf, e.g. `ParallelDownloads: 5`
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub pacman_options: BTreeMap<String, OptionValue>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub keys: Vec<Key>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub local_repository: Option<LocalRepository>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mirrors: Option<MirrorStrategy>,
+    // Rendered to `<name>.hook` in pacman's ini format
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub hooks: BTreeMap<String, Hook>,
+}
+
+// A package file is either a plain list of packages or a mapping of sections
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(untagged)]
+pub enum PackageFile {
+    List(Vec<PackageEntry>),
+    Sections(Sections),
+}
+
+impl Default for PackageFile {
+    fn default() -> Self {
+        PackageFile::List(Vec::new())
+    }
+}
+
+impl PackageFile {
+    pub fn read(path: &Path) -> Result<Self, Box<dyn std::error::Error>> {
+        let file = File::open(path)?;
+        Ok(serde_yaml_ng::from_reader(BufReader::new(file))?)
+    }
+
+    pub fn write(&self, path: &Path) -> Result<(), Box<dyn std::error::Error>> {
+        let content = serde_yaml_ng::to_string(self)?;
+        write_privileged_file(&path.to_string_lossy(), &content)
+    }
+
+    pub fn packages(&self) -> &[PackageEntry] {
+        match self {
+            PackageFile::List(packages) => packages,
+            PackageFile::Sections(sections) => &sections.packages,
+        }
+    }
+
+    pub fn packages_mut(&mut self) -> &mut Vec<PackageEntry> {
+        match self {
+            PackageFile::List(packages) => packages,
+            PackageFile::Sections(sections) => &mut sections.packages,
+        }
+    }
+
+    pub fn repositories(&self) -> &[Repository] {
+        match self {
+            PackageFile::List(_) => &[],
+            PackageFile::Sections(sections) => &sections.repositories,
+        }
+    }
+
+    pub fn keys(&self) -> &[Key] {
+        match self {
+            PackageFile::List(_) => &[],
+            PackageFile::Sections(sections) => &sections.keys,
+        }
+    }
+
+    pub fn local_repository(&self) -> Option<&LocalRepository> {
+        match self {
+            PackageFile::List(_) => None,
+            PackageFile::Sections(sections) => sections.local_repository.as_ref(),
+        }
+    }
+
+    pub fn mirrors(&self) -> Option<&MirrorStrategy> {
+        match self {
+            PackageFile::List(_) => None,
+            PackageFile::Sections(sections) => sections.mirrors.as_ref(),
+        }
+    }
+
+    pub fn hooks(&self) -> Option<&BTreeMap<String, Hook>> {
+        match self {
+            PackageFile::List(_) => None,
+            PackageFile::Sections(sections) => Some(&sections.hooks),
+        }
+    }
+
+    pub fn pacman_options(&self) -> Option<&BTreeMap<String, OptionValue>> {
+        match self {
+            PackageFile::List(_) => None,
+            PackageFile::Sections(sections) => Some(&sections.pacman_options),
+        }
+    }
+
+    // Nothing left worth keeping the file for
+    pub fn is_empty(&self) -> bool {
+        self.packages().is_empty()
+            && self.repositories().is_empty()
+            && self.pacman_options().is_none_or(BTreeMap::is_empty)
+            && self.keys().is_empty()
+            && self.local_repository().is_none()
+            && self.mirrors().is_none()
+            && self.hooks().is_none_or(BTreeMap::is_empty)
+    }
+}
+
+// Every yaml file of the packages folder with its file name
+fn read_files(folder: &str) -> Result<Vec<(String, PackageFile)>, Box<dyn std::error::Error>> {
+    let mut files = Vec::new();
+    for entry in fs::read_dir(folder)? {
+        let path = entry?.path();
+        if !path.is_file() || path.extension().is_none_or(|ext| ext != "yaml") {
+            continue;
+        }
+        let Some(filename) = path.file_name().and_then(|name| name.to_str()) else {
+            continue;
+        };
+        let file = PackageFile::read(&path).map_err(|e| format!("{}: {}", filename, e))?;
+        files.push((filename.to_string(), file));
+    }
+    // Read in a stable order, the first of two declarations is the one kept
+    files.sort_by(|(a, _), (b, _)| a.cmp(b));
+    Ok(files)
+}
+
+// Everything besides packages that the packages folder declares, read once
+// per run. A name declared in two files keeps its first declaration.
+#[derive(Debug, Default)]
+pub struct FolderDeclarations {
+    // Paths in the declarations, such as a static mirrorlist, are relative to it
+    pub folder: String,
+    pub repositories: Vec<Repository>,
+    pub pacman_options: BTreeMap<String, OptionValue>,
+    pub keys: Vec<Key>,
+    pub local_repository: Option<LocalRepository>,
+    pub mirrors: Option<MirrorStrategy>,
+    pub hooks: BTreeMap<String, Hook>,
+}
+
+pub fn warn_redeclared(what: &str, filename: &str) {
+    eprintln!(
+        "{} {} declared again in {}, keeping the first one",
+        YELLOW_WARNING, what, filename
+    );
+}
+
+impl FolderDeclarations {
+    pub fn read(folder: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut declared = FolderDeclarations {
+            folder: folder.to_string(),
+            ..Default::default()
+        };
+        for (filename, file) in read_files(folder)? {
+            let PackageFile::Sections(sections) = file else {
+                continue;
+            };
+            for repository in sections.repositories {
+                if declared.repositories.iter().any(|known| known.name == repository.name) {
+                    warn_redeclared(&format!("Repository {}", repository.name), &filename);
+                    continue;
+                }
+                declared.repositories.push(repository);
+            }
+            for (key, value) in sections.pacman_options {
+                if declared.pacman_options.contains_key(&key) {
+                    warn_redeclared(&format!("Option {}", key), &filename);
+                    continue;
+                }
+                declared.pacman_options.insert(key, value);
+            }
+            declared.keys.extend(sections.keys);
+            if let Some(local) = sections.local_repository {
+                match declared.local_repository {
+                    Some(_) => warn_redeclared("Local repository", &filename),
+                    None => declared.local_repository = Some(local),
+                }
+            }
+            if let Some(mirrors) = sections.mirrors {
+                match declared.mirrors {
+                    Some(_) => warn_redeclared("Mirrors", &filename),
+                    None => declared.mirrors = Some(mirrors),
+                }
+            }
+            for (name, hook) in sections.hooks {
+                if declared.hooks.contains_key(&name) {
+                    warn_redeclared(&format!("Hook {}", name), &filename);
+                    continue;
+                }
+                declared.hooks.insert(name, hook);
+            }
+        }
+        Ok(declared)
+    }
+}
+
+pub fn read_declarations(folder: &str) -> Result<Vec<Declaration>, Box<dyn std::error::Error>> {
+    let mut declarations = Vec::new();
+    for (filename, file) in read_files(folder)? {
+        declarations.extend(file.packages().iter().map(|entry| Declaration {
+            name: entry.name().to_string(),
+            file: filename.clone(),
+            repo: entry.repo().map(str::to_string),
+        }));
+    }
+    Ok(declarations)
+}
+
+pub fn declaring_file<'a>(declarations: &'a [Declaration], package: &str) -> Option<&'a str> {
+    declarations
+        .iter()
+        .find(|declaration| declaration.name == package)
+        .map(|declaration| declaration.file.as_str())
+}
+
+// What paru or pacman is asked for: `repo/name` when the declaration pins one
+pub fn install_target(declarations: &[Declaration], package: &str) -> String {
+    declarations
+        .iter()
+        .find(|declaration| declaration.name == package)
+        .and_then(|declaration| declaration.repo.as_ref())
+        .map_or(package.to_string(), |repo| format!("{}/{}", repo, package))
+}
@@ -0,0 +1,275 @@
+// Classification of pacman/paru failures and the policy applied to each class
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum FailureClass {
+    Network,
+    Signature,
+    Conflict,
+    MissingTarget,
+    LockHeld,
+    DiskFull,
+    Unknown,
+}
+
+impl fmt::Display for FailureClass {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let label = match self {
+            FailureClass::Network => "Network/Download",
+            FailureClass::Signature => "Signature/Keyring",
+            FailureClass::Conflict => "Conflict",
+            FailureClass::MissingTarget => "Missing target",
+            FailureClass::LockHeld => "Database lock held",
+            FailureClass::DiskFull => "Disk full",
+            FailureClass::Unknown => "Unknown",
+        };
+        write!(f, "{}", label)
+    }
+}
+
+// Checked in order, the more specific classes come first so that e.g. a
+// signature failure while downloading is not mistaken for a network error
+const LOCK_PATTERNS: &[&str] = &[
+    "unable to lock database",
+    "could not lock database",
+    "db.lck",
+];
+
+const DISK_PATTERNS: &[&str] = &[
+    "not enough free disk space",
+    "no space left on device",
+];
+
+const SIGNATURE_PATTERNS: &[&str] = &[
+    "pgp signature",
+    "invalid or corrupted package",
+    "invalid or corrupted database",
+    "unknown trust",
+    "signature is invalid",
+    "signature from",
+    "missing from keyring",
+    "required key missing",
+    "could not be looked up remotely",
+    "keyring is not writable",
+    "public key not found",
+];
+
+const CONFLICT_PATTERNS: &[&str] = &[
+    "conflicting files",
+    "exists in filesystem",
+    "conflicting dependencies",
+    "are in conflict",
+    "unresolvable package conflicts",
+    "could not satisfy dependencies",
+    "breaks dependency",
+];
+
+const MISSING_PATTERNS: &[&str] = &[
+    "target not found",
+    "could not find all required packages",
+    "no aur package found",
+    "unable to find",
+];
+
+const NETWORK_PATTERNS: &[&str] = &[
+    "failed retrieving file",
+    "failed to retrieve",
+    "failed to download",
+    "download failed",
+    "connection timed out",
+    "operation timed out",
+    "operation too slow",
+    "connection refused",
+    "connection reset",
+    "could not resolve host",
+    "temporary failure in name resolution",
+    "network is unreachable",
+    "could not connect",
+    "no route to host",
+    "ssl connect error",
+    "requested url returned error",
+    "http error 404",
+    "http error 502",
+    "http error 503",
+];
+
+pub fn classify(error_output: &str) -> FailureClass {
+    let error_lower = error_output.to_lowercase();
+    let matches = |patterns: &[&str]| patterns.iter().any(|pattern| error_lower.contains(pattern));
+
+    if matches(LOCK_PATTERNS) {
+        FailureClass::LockHeld
+    } else if matches(DISK_PATTERNS) {
+        FailureClass::DiskFull
+    } else if matches(SIGNATURE_PATTERNS) {
+        FailureClass::Signature
+    } else if matches(CONFLICT_PATTERNS) {
+        FailureClass::Conflict
+    } else if matches(MISSING_PATTERNS) {
+        FailureClass::MissingTarget
+    } else if matches(NETWORK_PATTERNS) {
+        FailureClass::Network
+    } else {
+        FailureClass::Unknown
+    }
+}
+
+// Longest wait between two retries, whatever backoff is configured
+const MAX_RETRY_DELAY: u64 = 300;
+
+// Seconds to wait before retry n (1-based) of a Retry policy
+pub fn retry_delay(backoff: u64, retry: u32) -> u64 {
+    let factor = 2u64.checked_pow(retry.saturating_sub(1)).unwrap_or(u64::MAX);
+    backoff.saturating_mul(factor).min(MAX_RETRY_DELAY)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(tag = "action", rename_all = "kebab-case")]
+pub enum Policy {
+    // Retry up to `attempts` times, waiting `backoff * 2^(n-1)` seconds before retry n,
+    // at most MAX_RETRY_DELAY
+    Retry { attempts: u32, backoff: u64 },
+    // Refresh the pacman keyring once, then retry
+    RefreshKeyring,
+    Abort,
+    Ask,
+}
+
+impl fmt::Display for Policy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Policy::Retry { attempts, backoff } => write!(
+                f,
+                "retry up to {} times with {}s exponential backoff",
+                attempts, backoff
+            ),
+            Policy::RefreshKeyring => write!(f, "refresh keyring and retry"),
+            Policy::Abort => write!(f, "abort"),
+            Policy::Ask => write!(f, "ask"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct FailurePolicies {
+    pub network: Policy,
+    pub signature: Policy,
+    pub conflict: Policy,
+    pub missing_target: Policy,
+    pub lock_held: Policy,
+    pub disk_full: Policy,
+    pub unknown: Policy,
+}
+
+impl Default for FailurePolicies {
+    fn default() -> Self {
+        FailurePolicies {
+            network: Policy::Retry { attempts: 3, backoff: 5 },
+            signature: Policy::RefreshKeyring,
+            conflict: Policy::Abort,
+            missing_target: Policy::Abort,
+            lock_held: Policy::Retry { attempts: 5, backoff: 2 },
+            disk_full: Policy::Abort,
+            unknown: Policy::Ask,
+        }
+    }
+}
+
+impl FailurePolicies {
+    pub fn for_class(&self, class: FailureClass) -> &Policy {
+        match class {
+            FailureClass::Network => &self.network,
+            FailureClass::Signature => &self.signature,
+            FailureClass::Conflict => &self.conflict,
+            FailureClass::MissingTarget => &self.missing_target,
+            FailureClass::LockHeld => &self.lock_held,
+            FailureClass::DiskFull => &self.disk_full,
+            FailureClass::Unknown => &self.unknown,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn signature_error_while_downloading_is_a_signature_failure() {
+        let stderr = "\
+error: linux: signature from \"Jan Alexander Steffens (heftig) <heftig@archlinux.org>\" is unknown trust
+:: File /var/cache/pacman/pkg/linux-6.9.7.arch1-1-x86_64.pkg.tar.zst is corrupted (invalid or corrupted package (PGP signature)).
+Do you want to delete it? [Y/n]
+error: failed to commit transaction (invalid or corrupted package (PGP signature))
+Errors occurred, no packages were upgraded.";
+        assert_eq!(classify(stderr), FailureClass::Signature);
+    }
+
+    #[test]
+    fn missing_key_is_a_signature_failure() {
+        let stderr = "\
+error: chaotic-keyring: key \"EF925EA60F33D0CB85C44AD13056513887B78AEB\" is unknown
+:: Import PGP key EF925EA60F33D0CB85C44AD13056513887B78AEB? [Y/n]
+error: key \"EF925EA60F33D0CB85C44AD13056513887B78AEB\" could not be looked up remotely
+error: required key missing from keyring";
+        assert_eq!(classify(stderr), FailureClass::Signature);
+    }
+
+    #[test]
+    fn lock_held() {
+        let stderr = "\
+error: failed to init transaction (unable to lock database)
+error: could not lock database: File exists
+  if you're sure a package manager is not already
+  running, you can remove /var/lib/pacman/db.lck";
+        assert_eq!(classify(stderr), FailureClass::LockHeld);
+    }
+
+    #[test]
+    fn missing_targets() {
+        assert_eq!(classify("error: target not found: vscodium"), FailureClass::MissingTarget);
+        assert_eq!(
+            classify("error: Could not find all required packages:\n    foo-git (target)"),
+            FailureClass::MissingTarget
+        );
+    }
+
+    #[test]
+    fn conflicts() {
+        let stderr = "\
+error: failed to commit transaction (conflicting files)
+nodejs: /usr/bin/node exists in filesystem
+Errors occurred, no packages were upgraded.";
+        assert_eq!(classify(stderr), FailureClass::Conflict);
+        assert_eq!(
+            classify(":: installing python (3.12.4-1) breaks dependency 'python<3.12' required by python-foo"),
+            FailureClass::Conflict
+        );
+    }
+
+    #[test]
+    fn network_and_disk() {
+        let stderr = "\
+error: failed retrieving file 'core.db' from geo.mirror.pkgbuild.com : Could not resolve host: geo.mirror.pkgbuild.com
+warning: fatal error from geo.mirror.pkgbuild.com, skipping for the remainder of this transaction
+error: failed to synchronize all databases (download library error)";
+        assert_eq!(classify(stderr), FailureClass::Network);
+        assert_eq!(
+            classify("error: Partition / too full: 2612 blocks needed, 1024 blocks free\nerror: not enough free disk space"),
+            FailureClass::DiskFull
+        );
+        assert_eq!(classify("error: something else went wrong"), FailureClass::Unknown);
+    }
+
+    #[test]
+    fn retry_delay_doubles_up_to_the_cap() {
+        assert_eq!(retry_delay(5, 1), 5);
+        assert_eq!(retry_delay(5, 2), 10);
+        assert_eq!(retry_delay(5, 3), 20);
+        assert_eq!(retry_delay(5, 7), MAX_RETRY_DELAY);
+        assert_eq!(retry_delay(0, 10), 0);
+        assert_eq!(retry_delay(2, 64), MAX_RETRY_DELAY);
+        assert_eq!(retry_delay(u64::MAX, u32::MAX), MAX_RETRY_DELAY);
+    }
+}
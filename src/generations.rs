@@ -0,0 +1,195 @@
+// Numbered snapshots of the declared and installed package sets, recorded
+// after every successful apply
+use crate::{
+    BLUE_GEAR, GREEN_CHECK, RED_CROSS, YELLOW_WARNING, create_directory, database, paths,
+    read_system_file, timestamp, write_privileged_file,
+};
+use alpm::PackageReason;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::BufReader;
+use std::process::{Command, exit};
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct InstalledPackage {
+    pub version: String,
+    pub arch: String,
+    // Installed as a dependency, generations recorded before this was kept
+    // read as explicit
+    #[serde(default)]
+    pub dependency: bool,
+}
+
+impl InstalledPackage {
+    pub fn same_build(&self, other: &InstalledPackage) -> bool {
+        self.version == other.version && self.arch == other.arch
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Generation {
+    pub number: u32,
+    pub timestamp: u64,
+    pub command: String,
+    pub revision: Option<String>,
+    pub declared: Vec<String>,
+    pub installed: BTreeMap<String, InstalledPackage>,
+}
+
+fn generations_directory() -> String {
+    paths::state_file("generations")
+}
+
+fn generation_file(number: u32) -> String {
+    format!("{}/{}.yaml", generations_directory(), number)
+}
+
+pub fn numbers() -> Vec<u32> {
+    let mut numbers: Vec<u32> = match fs::read_dir(generations_directory()) {
+        Ok(entries) => entries
+            .flatten()
+            .filter_map(|entry| {
+                entry
+                    .file_name()
+                    .to_str()
+                    .and_then(|name| name.strip_suffix(".yaml"))
+                    .and_then(|number| number.parse().ok())
+            })
+            .collect(),
+        Err(_) => Vec::new(),
+    };
+    numbers.sort_unstable();
+    numbers
+}
+
+pub fn load(number: u32) -> Result<Generation, Box<dyn std::error::Error>> {
+    let file = File::open(generation_file(number))?;
+    let generation = serde_yaml_ng::from_reader(BufReader::new(file))?;
+    Ok(generation)
+}
+
+pub fn installed_versions() -> BTreeMap<String, InstalledPackage> {
+    let alpm = database::open();
+    alpm.localdb()
+        .pkgs()
+        .iter()
+        .map(|pkg| {
+            let installed = InstalledPackage {
+                version: pkg.version().to_string(),
+                arch: pkg.arch().unwrap_or("any").to_string(),
+                dependency: pkg.reason() == PackageReason::Depend,
+            };
+            (pkg.name().to_string(), installed)
+        })
+        .collect()
+}
+
+// HEAD of the packages folder if it is a git checkout, marked when it has
+// uncommitted changes
+fn folder_revision(folder: &str) -> Option<String> {
+    let output = Command::new("git")
+        .args(["-C", folder, "rev-parse", "HEAD"])
+        .output()
+        .ok()
+        .filter(|output| output.status.success())?;
+    let revision = String::from_utf8_lossy(&output.stdout).trim().to_string();
+
+    let dirty = Command::new("git")
+        .args(["-C", folder, "status", "--porcelain"])
+        .output()
+        .map(|output| !output.stdout.is_empty())
+        .unwrap_or(false);
+
+    Some(if dirty { format!("{}-dirty", revision) } else { revision })
+}
+
+fn write_generation(generation: &Generation) -> Result<(), Box<dyn std::error::Error>> {
+    let yaml_content = serde_yaml_ng::to_string(generation)?;
+    write_privileged_file(&generation_file(generation.number), &yaml_content)
+}
+
+pub fn record(command: &str) {
+    let config = match read_system_file() {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("{} Not recording generation: {}", YELLOW_WARNING, e);
+            return;
+        }
+    };
+    let installed = installed_versions();
+
+    let previous = numbers().last().copied();
+    if let Some(last) = previous.and_then(|number| load(number).ok())
+        && last.declared == config.packages
+        && last.installed == installed
+    {
+        println!("{} No changes since generation {}", GREEN_CHECK, last.number);
+        return;
+    }
+
+    let generation = Generation {
+        number: previous.unwrap_or(0) + 1,
+        timestamp: timestamp::now(),
+        command: command.to_string(),
+        revision: folder_revision(&config.folder),
+        declared: config.packages,
+        installed,
+    };
+
+    let directory = generations_directory();
+    if !create_directory(&directory) {
+        eprintln!("{} Failed to create {}", RED_CROSS, directory);
+        return;
+    }
+
+    match write_generation(&generation) {
+        Ok(_) => println!("{} Recorded generation {}", GREEN_CHECK, generation.number),
+        Err(e) => eprintln!("{} Failed to record generation: {}", RED_CROSS, e),
+    }
+}
+
+pub fn list() {
+    let numbers = numbers();
+    if numbers.is_empty() {
+        println!("{} No generations recorded yet", YELLOW_WARNING);
+        return;
+    }
+
+    for number in numbers {
+        match load(number) {
+            Ok(generation) => println!(
+                "{:>4}  {}  {:>4} declared  {:>5} installed  {}  {}",
+                generation.number,
+                timestamp::format(generation.timestamp),
+                generation.declared.len(),
+                generation.installed.len(),
+                generation.revision.as_deref().map_or("-", |rev| &rev[..rev.len().min(12)]),
+                generation.command
+            ),
+            Err(e) => eprintln!("{} Failed to read generation {}: {}", RED_CROSS, number, e),
+        }
+    }
+}
+
+pub fn show(number: u32) {
+    let generation = load(number).unwrap_or_else(|e| {
+        eprintln!("{} Failed to read generation {}: {}", RED_CROSS, number, e);
+        exit(1);
+    });
+
+    println!("\nGeneration : {}", generation.number);
+    println!("Date : {}", timestamp::format(generation.timestamp));
+    println!("Command : {}", generation.command);
+    println!("Revision : {}", generation.revision.as_deref().unwrap_or("-"));
+
+    println!("\n{} Declared packages ({})", BLUE_GEAR, generation.declared.len());
+    for package in &generation.declared {
+        println!("  {}", package);
+    }
+
+    println!("\n{} Installed packages ({})", BLUE_GEAR, generation.installed.len());
+    for (name, installed) in &generation.installed {
+        println!("  {} {} ({})", name, installed.version, installed.arch);
+    }
+}
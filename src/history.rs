@@ -0,0 +1,228 @@
+// Timeline of package changes from pacman.log, attributed to this tool
+// through its own append-only action log
+use crate::{RED_CROSS, YELLOW_WARNING, append_privileged_file, paths, timestamp};
+use clap::ValueEnum;
+use std::fs;
+use std::process::exit;
+
+const PACMAN_LOG: &str = "/var/log/pacman.log";
+// Any package touched while this action ran, older logs name single packages
+const ANY_PACKAGE: &str = "*";
+// pacman.log only has second precision and is written slightly apart from us
+const MATCH_SLACK: u64 = 2;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum HistorySource {
+    Tool,
+    External,
+}
+
+struct Action {
+    started: u64,
+    finished: u64,
+    action: String,
+    package: String,
+    reason: String,
+}
+
+struct Event {
+    time: u64,
+    action: String,
+    package: String,
+    details: String,
+}
+
+fn action_log() -> String {
+    paths::state_file("actions.log")
+}
+
+// One line per transaction: started, finished, action, package and reason, tab
+// separated. Every package pacman touched in that window is attributed, so
+// dependencies and recursive removals are not shown as external.
+pub fn record(action: &str, reason: &str, started: u64) {
+    let line = format!(
+        "{}\t{}\t{}\t{}\t{}\n",
+        started,
+        timestamp::now(),
+        action,
+        ANY_PACKAGE,
+        reason
+    );
+    if let Err(e) = append_privileged_file(&action_log(), &line) {
+        eprintln!("{} Failed to write action log: {}", YELLOW_WARNING, e);
+    }
+}
+
+fn read_actions() -> Vec<Action> {
+    fs::read_to_string(action_log())
+        .unwrap_or_default()
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.splitn(5, '\t');
+            Some(Action {
+                started: fields.next()?.parse().ok()?,
+                finished: fields.next()?.parse().ok()?,
+                action: fields.next()?.to_string(),
+                package: fields.next()?.to_string(),
+                reason: fields.next().unwrap_or_default().to_string(),
+            })
+        })
+        .collect()
+}
+
+// `[2024-05-01T10:00:00+0200] [ALPM] upgraded foo (1.0-1 -> 1.1-1)`
+fn parse_event(line: &str) -> Option<Event> {
+    let rest = line.strip_prefix('[')?;
+    let (time, rest) = rest.split_once(']')?;
+    let rest = rest.trim_start().strip_prefix("[ALPM]")?.trim_start();
+
+    let (action, rest) = rest.split_once(' ')?;
+    if !matches!(action, "installed" | "upgraded" | "downgraded" | "reinstalled" | "removed") {
+        return None;
+    }
+    let (package, details) = match rest.split_once(' ') {
+        Some((package, details)) => (package, details.trim_matches(|c| c == '(' || c == ')')),
+        None => (rest, ""),
+    };
+
+    Some(Event {
+        time: timestamp::parse_pacman(time)?,
+        action: action.to_string(),
+        package: package.to_string(),
+        details: details.to_string(),
+    })
+}
+
+fn attribute<'a>(event: &Event, actions: &'a [Action]) -> Option<&'a Action> {
+    actions.iter().find(|action| {
+        (action.package == event.package || action.package == ANY_PACKAGE)
+            && event.time + MATCH_SLACK >= action.started
+            && event.time <= action.finished + MATCH_SLACK
+    })
+}
+
+fn parse_bound(value: Option<&str>, flag: &str) -> Option<u64> {
+    value.map(|date| {
+        timestamp::parse_date(date).unwrap_or_else(|| {
+            eprintln!("{} Invalid {} date {}, expected YYYY-MM-DD", RED_CROSS, flag, date);
+            exit(1);
+        })
+    })
+}
+
+// Inclusive, the whole --until day is shown
+fn until_bound(value: Option<&str>) -> Option<u64> {
+    parse_bound(value, "--until").map(|date| date + 86_400)
+}
+
+fn within(time: u64, since: Option<u64>, until: Option<u64>) -> bool {
+    since.is_none_or(|since| time >= since) && until.is_none_or(|until| time < until)
+}
+
+pub fn history(
+    since: Option<&str>,
+    until: Option<&str>,
+    package: Option<&str>,
+    source: Option<HistorySource>,
+) {
+    let since = parse_bound(since, "--since");
+    let until = until_bound(until);
+
+    let log_path = paths::target(PACMAN_LOG);
+    let log = fs::read_to_string(&log_path).unwrap_or_else(|e| {
+        eprintln!("{} Failed to read {}: {}", RED_CROSS, log_path, e);
+        exit(1);
+    });
+    let actions = read_actions();
+
+    let mut shown = 0;
+    for event in log.lines().filter_map(parse_event) {
+        if !within(event.time, since, until)
+            || package.is_some_and(|package| event.package != package)
+        {
+            continue;
+        }
+
+        let attributed = attribute(&event, &actions);
+        let matches_source = match source {
+            Some(HistorySource::Tool) => attributed.is_some(),
+            Some(HistorySource::External) => attributed.is_none(),
+            None => true,
+        };
+        if !matches_source {
+            continue;
+        }
+
+        let origin = match attributed {
+            Some(action) => format!("tool: {} ({})", action.action, action.reason),
+            None => "external".to_string(),
+        };
+        println!(
+            "{}  {:<11} {} {}  [{}]",
+            timestamp::format(event.time),
+            event.action,
+            event.package,
+            event.details,
+            origin
+        );
+        shown += 1;
+    }
+
+    if shown == 0 {
+        println!("{} No matching package changes", YELLOW_WARNING);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_package_changes() {
+        let event =
+            parse_event("[2024-05-01T12:00:00+0200] [ALPM] upgraded linux (6.8.9.arch1-1 -> 6.9.1.arch1-1)")
+                .unwrap();
+        assert_eq!(event.time, 1_714_557_600);
+        assert_eq!(event.action, "upgraded");
+        assert_eq!(event.package, "linux");
+        assert_eq!(event.details, "6.8.9.arch1-1 -> 6.9.1.arch1-1");
+
+        let event = parse_event("[2019-01-01 10:00] [ALPM] removed vim (8.1.0-1)").unwrap();
+        assert_eq!(event.time, 1_546_336_800);
+        assert_eq!(event.action, "removed");
+        assert_eq!(event.package, "vim");
+        assert_eq!(event.details, "8.1.0-1");
+    }
+
+    #[test]
+    fn skips_lines_that_are_not_package_changes() {
+        for line in [
+            "[2024-05-01T12:00:00+0200] [PACMAN] Running 'pacman -Syu'",
+            "[2024-05-01T12:00:00+0200] [ALPM] transaction started",
+            "[2024-05-01T12:00:00+0200] [ALPM-SCRIPTLET] installed foo",
+            "[2024-05-01T12:00:00+0200] [ALPM] running '60-mkinitcpio-remove.hook'...",
+            "[not a date] [ALPM] installed foo (1.0-1)",
+            "",
+        ] {
+            assert!(parse_event(line).is_none(), "{}", line);
+        }
+    }
+
+    #[test]
+    fn until_includes_the_whole_day() {
+        let until = until_bound(Some("2024-05-01"));
+        let time = |line: &str| parse_event(line).unwrap().time;
+
+        let last_second = time("[2024-05-01T23:59:59+0000] [ALPM] installed foo (1.0-1)");
+        let next_day = time("[2024-05-02T00:00:00+0000] [ALPM] installed foo (1.0-1)");
+        // Still the 1st in local time, but already the 2nd in UTC
+        let behind_utc = time("[2024-05-01T20:00:00-0500] [ALPM] installed foo (1.0-1)");
+        assert!(within(last_second, None, until));
+        assert!(!within(next_day, None, until));
+        assert!(!within(behind_utc, None, until));
+
+        let since = parse_bound(Some("2024-05-01"), "--since");
+        assert!(within(last_second, since, until));
+        assert!(!within(last_second - 86_400, since, until));
+    }
+}
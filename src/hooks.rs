@@ -0,0 +1,224 @@
+// pacman hooks declared in the packages folder, installed into the target's
+// hooks directory
+use crate::declarations::{FolderDeclarations, warn_redeclared};
+use crate::pacman_conf::diff;
+use crate::{
+    BLUE_GEAR, Config, GREEN_CHECK, RED_CROSS, ask_confirmation, create_directory, load_config,
+    paths, privilege, save_systemfile, write_privileged_file,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+const HOOKS_DIRECTORY: &str = "/etc/pacman.d/hooks";
+// Folder inside the packages folder with .hook files installed verbatim
+const VERBATIM_DIRECTORY: &str = "hooks";
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Trigger {
+    pub operation: Vec<String>,
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub target: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Action {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    pub when: String,
+    pub exec: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub depends: Vec<String>,
+    #[serde(default)]
+    pub abort_on_fail: bool,
+    #[serde(default)]
+    pub needs_targets: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Hook {
+    pub triggers: Vec<Trigger>,
+    pub action: Action,
+}
+
+impl Hook {
+    // pacman's ini format, see alpm-hooks(5)
+    fn render(&self) -> String {
+        let mut lines = Vec::new();
+        for trigger in &self.triggers {
+            lines.push("[Trigger]".to_string());
+            lines.extend(
+                trigger
+                    .operation
+                    .iter()
+                    .map(|operation| format!("Operation = {}", operation)),
+            );
+            lines.push(format!("Type = {}", trigger.kind));
+            lines.extend(
+                trigger
+                    .target
+                    .iter()
+                    .map(|target| format!("Target = {}", target)),
+            );
+            lines.push(String::new());
+        }
+
+        lines.push("[Action]".to_string());
+        if let Some(description) = &self.action.description {
+            lines.push(format!("Description = {}", description));
+        }
+        lines.push(format!("When = {}", self.action.when));
+        lines.push(format!("Exec = {}", self.action.exec));
+        lines.extend(
+            self.action
+                .depends
+                .iter()
+                .map(|depend| format!("Depends = {}", depend)),
+        );
+        if self.action.abort_on_fail {
+            lines.push("AbortOnFail".to_string());
+        }
+        if self.action.needs_targets {
+            lines.push("NeedsTargets".to_string());
+        }
+        lines.join("\n") + "\n"
+    }
+}
+
+// Contents of every declared hook by file name
+pub fn declared(
+    declared: &FolderDeclarations,
+) -> Result<BTreeMap<String, String>, Box<dyn std::error::Error>> {
+    let mut hooks: BTreeMap<String, String> = declared
+        .hooks
+        .iter()
+        .map(|(name, hook)| (format!("{}.hook", name), hook.render()))
+        .collect();
+
+    let verbatim = Path::new(&declared.folder).join(VERBATIM_DIRECTORY);
+    if let Ok(entries) = fs::read_dir(&verbatim) {
+        let mut paths: Vec<_> = entries
+            .flatten()
+            .map(|entry| entry.path())
+            .filter(|path| path.is_file() && path.extension().is_some_and(|ext| ext == "hook"))
+            .collect();
+        paths.sort();
+        for path in paths {
+            let Some(filename) = path.file_name().and_then(|name| name.to_str()) else {
+                continue;
+            };
+            if hooks.contains_key(filename) {
+                warn_redeclared(
+                    &format!("Hook {}", filename),
+                    &verbatim.display().to_string(),
+                );
+                continue;
+            }
+            hooks.insert(filename.to_string(), fs::read_to_string(&path)?);
+        }
+    }
+    Ok(hooks)
+}
+
+fn save_managed_hooks(config: &mut Config, names: Vec<String>) {
+    if config.managed_hooks == names {
+        return;
+    }
+    config.managed_hooks = names;
+    if let Err(e) = save_systemfile(config) {
+        eprintln!("{} Failed to save systemfile: {}", RED_CROSS, e);
+    }
+}
+
+fn remove_hook(path: &str) -> bool {
+    match fs::remove_file(path) {
+        Ok(_) => true,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => true,
+        Err(_) => privilege::command("rm")
+            .args(["-f", "--", path])
+            .status()
+            .is_ok_and(|status| status.success()),
+    }
+}
+
+// Install declared hooks and remove managed ones that are no longer declared,
+// after showing what changes
+pub fn converge(declared: &FolderDeclarations) {
+    let hooks = match self::declared(declared) {
+        Ok(hooks) => hooks,
+        Err(e) => {
+            eprintln!("{} Error reading hooks :: {}", RED_CROSS, e);
+            return;
+        }
+    };
+    let directory = paths::target(HOOKS_DIRECTORY);
+    let hook_path = |name: &str| format!("{}/{}", directory, name);
+
+    let mut config = load_config();
+    let declared_names: Vec<String> = hooks.keys().cloned().collect();
+    let changed: Vec<(&String, &String)> = hooks
+        .iter()
+        .filter(|(name, content)| {
+            !fs::read_to_string(hook_path(name)).is_ok_and(|current| &current == *content)
+        })
+        .collect();
+    let removed: Vec<String> = config
+        .managed_hooks
+        .iter()
+        .filter(|name| !hooks.contains_key(*name))
+        .filter(|name| Path::new(&hook_path(name)).exists())
+        .cloned()
+        .collect();
+
+    if changed.is_empty() && removed.is_empty() {
+        save_managed_hooks(&mut config, declared_names);
+        return;
+    }
+
+    println!("{} Changes to {} :", BLUE_GEAR, directory);
+    for (name, content) in &changed {
+        let current = fs::read_to_string(hook_path(name)).unwrap_or_default();
+        println!("  {}", name);
+        for change in diff(&current, content) {
+            println!("    {}", change);
+        }
+    }
+    for name in &removed {
+        println!("  {} (no longer declared, removing)", name);
+    }
+    if !ask_confirmation("Do you want to apply these hook changes [Y/n] : ") {
+        return;
+    }
+
+    if !changed.is_empty() && !create_directory(&directory) {
+        eprintln!("{} Failed to create {}", RED_CROSS, directory);
+        return;
+    }
+    let mut failed = false;
+    for (name, content) in &changed {
+        if let Err(e) = write_privileged_file(&hook_path(name), content) {
+            eprintln!("{} Failed to write {}: {}", RED_CROSS, name, e);
+            failed = true;
+        }
+    }
+    for name in &removed {
+        if !remove_hook(&hook_path(name)) {
+            eprintln!("{} Failed to remove {}", RED_CROSS, name);
+            failed = true;
+        }
+    }
+
+    // A hook that could not be removed stays managed for the next run
+    let mut managed = declared_names;
+    managed.extend(
+        removed
+            .into_iter()
+            .filter(|name| Path::new(&hook_path(name)).exists()),
+    );
+    save_managed_hooks(&mut config, managed);
+    if !failed {
+        println!("{} Hooks updated", GREEN_CHECK);
+    }
+}
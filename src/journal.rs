@@ -0,0 +1,138 @@
+// Intent journal written before each install/remove batch so an interrupted
+// run can be reconciled against the local package database
+use crate::provenance::{self, Source};
+use crate::{
+    BLUE_GEAR, GREEN_CHECK, RED_CROSS, YELLOW_WARNING, database, declare_manual_install,
+    load_config, paths, save_systemfile, timestamp, write_privileged_file,
+};
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Operation {
+    Install,
+    Remove,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Journal {
+    pub operation: Operation,
+    pub packages: Vec<String>,
+    // How installed packages are tracked, journals written before it was
+    // kept read as folder installs
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source: Option<Source>,
+    pub started: u64,
+    pub completed: bool,
+}
+
+fn journal_file() -> String {
+    paths::state_file("journal.yaml")
+}
+
+fn write_journal(journal: &Journal) -> Result<(), Box<dyn std::error::Error>> {
+    let yaml_content = serde_yaml_ng::to_string(journal)?;
+    write_privileged_file(&journal_file(), &yaml_content)
+}
+
+fn read_journal() -> Option<Journal> {
+    let path = journal_file();
+    if !Path::new(&path).exists() {
+        return None;
+    }
+    match File::open(&path) {
+        Ok(file) => match serde_yaml_ng::from_reader(BufReader::new(file)) {
+            Ok(journal) => Some(journal),
+            Err(e) => {
+                eprintln!("{} Failed to parse journal: {}", YELLOW_WARNING, e);
+                None
+            }
+        },
+        Err(e) => {
+            eprintln!("{} Failed to open journal: {}", YELLOW_WARNING, e);
+            None
+        }
+    }
+}
+
+pub fn begin(operation: Operation, packages: &[String], source: Option<Source>) {
+    let journal = Journal {
+        operation,
+        packages: packages.to_vec(),
+        source,
+        started: timestamp::now(),
+        completed: false,
+    };
+    if let Err(e) = write_journal(&journal) {
+        eprintln!("{} Failed to write journal: {}", RED_CROSS, e);
+    }
+}
+
+pub fn complete() {
+    if let Some(mut journal) = read_journal() {
+        journal.completed = true;
+        if let Err(e) = write_journal(&journal) {
+            eprintln!("{} Failed to mark journal complete: {}", RED_CROSS, e);
+        }
+    }
+}
+
+pub fn has_pending() -> bool {
+    read_journal().is_some_and(|journal| !journal.completed)
+}
+
+// Bring system.yaml in line with what actually happened to the packages of
+// an unfinished batch, then close the journal
+pub fn resume() {
+    let Some(journal) = read_journal().filter(|journal| !journal.completed) else {
+        println!("{} Nothing to resume", GREEN_CHECK);
+        return;
+    };
+
+    println!(
+        "{} Reconciling unfinished {:?} of {} packages",
+        BLUE_GEAR,
+        journal.operation,
+        journal.packages.len()
+    );
+
+    let alpm = database::open();
+    let db = alpm.localdb();
+    let mut config = load_config();
+    let mut changed = false;
+    let source = journal.source.unwrap_or(Source::Folder);
+    let mut recorded = Vec::new();
+
+    for package in &journal.packages {
+        let installed = database::satisfied(db, package);
+        let tracked = config.packages.contains(package);
+        match journal.operation {
+            Operation::Install if installed && !tracked => {
+                println!("{} Recording installed package {}", GREEN_CHECK, package);
+                provenance::track(&mut config, std::slice::from_ref(package), source);
+                recorded.push(package.clone());
+                changed = true;
+            }
+            Operation::Remove if !installed && tracked => {
+                println!("{} Forgetting removed package {}", GREEN_CHECK, package);
+                provenance::untrack(&mut config, std::slice::from_ref(package));
+                changed = true;
+            }
+            _ => {}
+        }
+    }
+
+    if changed && let Err(e) = save_systemfile(&config) {
+        eprintln!("{} Failed to save systemfile: {}", RED_CROSS, e);
+        return;
+    }
+
+    complete();
+    if source == Source::Add {
+        declare_manual_install(&config.folder, &recorded);
+    }
+    println!("{} Journal reconciled", GREEN_CHECK);
+}
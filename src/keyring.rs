@@ -0,0 +1,254 @@
+// Signing keys declared in the packages folder, converged into pacman's keyring
+use crate::declarations::FolderDeclarations;
+use crate::snapshot::shell_quote;
+use crate::{
+    BLUE_GEAR, GREEN_CHECK, RED_CROSS, YELLOW_WARNING, is_offline, privilege, repositories,
+    run_command,
+};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::Path;
+
+const GPG_DIRECTORY: &str = "/etc/pacman.d/gnupg";
+// Keys shipped by keyring packages such as archlinux-keyring, like the
+// keyring itself these are the host's
+const PACKAGED_KEYRINGS: &str = "/usr/share/pacman/keyrings";
+const MASTER_KEY_UID: &str = "Pacman Keyring Master Key";
+
+fn default_local_sign() -> bool {
+    true
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Key {
+    // Full 40 hex digit fingerprint, short key IDs are refused
+    pub fingerprint: String,
+    #[serde(default = "default_local_sign")]
+    pub local_sign: bool,
+    // Imported from this file instead of a keyserver
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub keyfile: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub keyserver: Option<String>,
+}
+
+impl Key {
+    pub fn from_fingerprint(fingerprint: &str, keyserver: Option<String>) -> Self {
+        Key {
+            fingerprint: fingerprint.to_string(),
+            local_sign: true,
+            keyfile: None,
+            keyserver,
+        }
+    }
+
+    // Uppercase without the spaces gpg prints fingerprints with
+    fn normalized(&self) -> Option<String> {
+        let fingerprint: String = self
+            .fingerprint
+            .chars()
+            .filter(|c| !c.is_whitespace())
+            .collect::<String>()
+            .to_uppercase();
+        (fingerprint.len() == 40 && fingerprint.chars().all(|c| c.is_ascii_hexdigit()))
+            .then_some(fingerprint)
+    }
+}
+
+struct KeyringEntry {
+    fingerprint: String,
+    // Validity from gpg, full or ultimate once locally signed
+    trusted: bool,
+    master: bool,
+}
+
+// Primary keys of the keyring from gpg's colon listing
+fn list_keyring() -> Option<Vec<KeyringEntry>> {
+    if !Path::new(GPG_DIRECTORY).exists() {
+        return Some(Vec::new());
+    }
+    let output = privilege::command("gpg")
+        .args(["--homedir", GPG_DIRECTORY, "--no-permission-warning", "--with-colons", "--list-keys"])
+        .output()
+        .ok()
+        .filter(|output| output.status.success())?;
+
+    let mut entries: Vec<KeyringEntry> = Vec::new();
+    let mut validity = None;
+    for line in String::from_utf8_lossy(&output.stdout).lines() {
+        let fields: Vec<&str> = line.split(':').collect();
+        match fields.first() {
+            Some(&"pub") => validity = fields.get(1).map(|field| field.to_string()),
+            Some(&"fpr") => {
+                if let Some(validity) = validity.take() {
+                    entries.push(KeyringEntry {
+                        fingerprint: fields.get(9).unwrap_or(&"").to_uppercase(),
+                        trusted: validity == "f" || validity == "u",
+                        master: false,
+                    });
+                }
+            }
+            Some(&"uid") => {
+                if fields.get(9).is_some_and(|uid| uid.starts_with(MASTER_KEY_UID))
+                    && let Some(entry) = entries.last_mut()
+                {
+                    entry.master = true;
+                }
+            }
+            _ => {}
+        }
+    }
+    Some(entries)
+}
+
+// Fingerprints listed in the -trusted and -revoked files of keyring packages
+fn packaged_fingerprints() -> Vec<String> {
+    let Ok(entries) = fs::read_dir(PACKAGED_KEYRINGS) else {
+        return Vec::new();
+    };
+    entries
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| {
+            path.file_name()
+                .and_then(|name| name.to_str())
+                .is_some_and(|name| name.ends_with("-trusted") || name.ends_with("-revoked"))
+        })
+        .filter_map(|path| fs::read_to_string(path).ok())
+        .flat_map(|content| {
+            content
+                .lines()
+                .filter_map(|line| line.split(':').next())
+                .map(|fingerprint| fingerprint.trim().to_uppercase())
+                .filter(|fingerprint| !fingerprint.is_empty())
+                .collect::<Vec<_>>()
+        })
+        .collect()
+}
+
+pub fn declared(declared: &FolderDeclarations) -> Vec<Key> {
+    // Keyfiles are relative to the packages folder, not the working directory
+    let mut keys: Vec<Key> = declared
+        .keys
+        .iter()
+        .map(|key| Key {
+            keyfile: key.keyfile.as_ref().map(|keyfile| {
+                Path::new(&declared.folder).join(keyfile).to_string_lossy().into_owned()
+            }),
+            ..key.clone()
+        })
+        .collect();
+    // Including those of the declared repositories
+    for repository in repositories::declared(declared) {
+        keys.extend(
+            repository
+                .keys
+                .iter()
+                .map(|key| Key::from_fingerprint(key, repository.keyserver.clone())),
+        );
+    }
+    keys
+}
+
+// Import and locally sign the keys missing from the keyring, verifying the
+// imported fingerprint before trusting it
+pub fn ensure(keys: &[Key]) -> bool {
+    let mut all_ready = true;
+    let mut wanted = Vec::new();
+    for key in keys {
+        match key.normalized() {
+            Some(fingerprint) => wanted.push((fingerprint, key)),
+            None => {
+                eprintln!(
+                    "{} Key {} is not a full 40 hex digit fingerprint, skipping it",
+                    RED_CROSS, key.fingerprint
+                );
+                all_ready = false;
+            }
+        }
+    }
+    if wanted.is_empty() {
+        return all_ready;
+    }
+
+    if !Path::new(GPG_DIRECTORY).join("pubring.gpg").exists()
+        && !Path::new(GPG_DIRECTORY).join("pubring.kbx").exists()
+    {
+        run_command("pacman-key --init", true);
+        run_command("pacman-key --populate", true);
+    }
+    let Some(mut keyring) = list_keyring() else {
+        eprintln!("{} Failed to list the pacman keyring", RED_CROSS);
+        return false;
+    };
+
+    let mut imported = false;
+    let mut skipped = Vec::new();
+    for (fingerprint, key) in &wanted {
+        if keyring.iter().any(|entry| &entry.fingerprint == fingerprint) {
+            continue;
+        }
+        if key.keyfile.is_none() && is_offline() {
+            eprintln!("{} Offline, not fetching key {}", YELLOW_WARNING, fingerprint);
+            skipped.push(fingerprint.clone());
+            continue;
+        }
+        println!("{} Importing key {}", BLUE_GEAR, fingerprint);
+        let command = match (&key.keyfile, &key.keyserver) {
+            (Some(keyfile), _) => format!("pacman-key --add {}", shell_quote(keyfile)),
+            (None, Some(keyserver)) => {
+                format!("pacman-key --recv-keys {} --keyserver {}", fingerprint, keyserver)
+            }
+            (None, None) => format!("pacman-key --recv-keys {}", fingerprint),
+        };
+        run_command(&command, true);
+        imported = true;
+    }
+    if imported {
+        keyring = list_keyring().unwrap_or_default();
+    }
+
+    for (fingerprint, key) in &wanted {
+        if skipped.contains(fingerprint) {
+            all_ready = false;
+            continue;
+        }
+        // A keyfile or keyserver can hand out another key than the one declared
+        let Some(entry) = keyring.iter().find(|entry| &entry.fingerprint == fingerprint) else {
+            eprintln!(
+                "{} Key {} is not in the keyring after importing, not signing it",
+                RED_CROSS, fingerprint
+            );
+            all_ready = false;
+            continue;
+        };
+        if key.local_sign && !entry.trusted {
+            run_command(&format!("pacman-key --lsign-key {}", fingerprint), true);
+        }
+    }
+    all_ready
+}
+
+pub fn converge(declared: &FolderDeclarations) {
+    let keys = self::declared(declared);
+    if ensure(&keys) && !keys.is_empty() {
+        println!("{} Declared keys present in the pacman keyring", GREEN_CHECK);
+    }
+
+    let declared: Vec<String> = keys.iter().filter_map(Key::normalized).collect();
+    let packaged = packaged_fingerprints();
+    let undeclared: Vec<String> = list_keyring()
+        .unwrap_or_default()
+        .into_iter()
+        .filter(|entry| {
+            !entry.master && !declared.contains(&entry.fingerprint) && !packaged.contains(&entry.fingerprint)
+        })
+        .map(|entry| entry.fingerprint)
+        .collect();
+    if !undeclared.is_empty() {
+        println!("{} Keys in the pacman keyring that are not declared :", YELLOW_WARNING);
+        for fingerprint in &undeclared {
+            println!("  {}", fingerprint);
+        }
+    }
+}
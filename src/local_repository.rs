@@ -0,0 +1,213 @@
+// A file:// repository built from PKGBUILDs and prebuilt packages kept in the
+// packages folder
+use crate::declarations::FolderDeclarations;
+use crate::repositories::Repository;
+use crate::snapshot::shell_quote;
+use crate::{
+    BLUE_GEAR, GREEN_CHECK, RED_CROSS, YELLOW_WARNING, create_directory, is_offline,
+    noconfirm_flag, privilege, run_command,
+};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+fn default_sources() -> String {
+    "local".to_string()
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct LocalRepository {
+    pub name: String,
+    // Where the packages and the repository database are kept
+    pub directory: String,
+    // Folder inside the packages folder with a directory per PKGBUILD and
+    // prebuilt .pkg.tar.zst files
+    #[serde(default = "default_sources")]
+    pub sources: String,
+}
+
+impl LocalRepository {
+    // Its pacman.conf section, the packages are built locally and unsigned
+    pub fn repository(&self) -> Repository {
+        Repository {
+            name: self.name.clone(),
+            server: vec![format!("file://{}", self.directory)],
+            include: None,
+            sig_level: Some("Optional TrustAll".to_string()),
+            keys: Vec::new(),
+            keyserver: None,
+            bootstrap: Vec::new(),
+        }
+    }
+
+    pub fn database(&self) -> PathBuf {
+        Path::new(&self.directory).join(format!("{}.db.tar.gz", self.name))
+    }
+}
+
+fn is_package_file(path: &Path) -> bool {
+    path.is_file()
+        && path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .is_some_and(|name| name.contains(".pkg.tar.") && !name.ends_with(".sig"))
+}
+
+// makepkg never runs as root, like paru
+fn makepkg() -> Command {
+    let prefix = privilege::user_prefix();
+    let mut words = prefix.split_whitespace();
+    let mut command = match words.next() {
+        Some(program) => {
+            let mut command = Command::new(program);
+            command.args(words).arg("makepkg");
+            command
+        }
+        None => Command::new("makepkg"),
+    };
+    command.stdin(Stdio::null());
+    command
+}
+
+// Files makepkg would produce for this PKGBUILD at its current version
+fn package_list(directory: &Path) -> Option<Vec<PathBuf>> {
+    let output = makepkg()
+        .arg("-D")
+        .arg(directory)
+        .arg("--packagelist")
+        .output()
+        .ok()
+        .filter(|output| output.status.success())?;
+    Some(
+        String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .map(PathBuf::from)
+            .collect(),
+    )
+}
+
+// Build a PKGBUILD unless the repository already has its current version,
+// returning the packages to add
+fn build_package(directory: &Path, local: &LocalRepository) -> Vec<PathBuf> {
+    let Some(listed) = package_list(directory) else {
+        eprintln!("{} Failed to read the PKGBUILD in {}", RED_CROSS, directory.display());
+        return Vec::new();
+    };
+    let built = listed.iter().all(|package| {
+        package
+            .file_name()
+            .is_some_and(|name| Path::new(&local.directory).join(name).exists())
+    });
+    if built {
+        return Vec::new();
+    }
+    // Sources and build dependencies are downloaded
+    if is_offline() {
+        println!("{} Offline, not building {}", YELLOW_WARNING, directory.display());
+        return Vec::new();
+    }
+
+    println!("{} Building {}", BLUE_GEAR, directory.display());
+    let command = format!(
+        "makepkg -D {} --syncdeps --force{}",
+        shell_quote(&directory.to_string_lossy()),
+        noconfirm_flag()
+    );
+    if !run_command(&command, false) {
+        return Vec::new();
+    }
+    listed.into_iter().filter(|package| package.exists()).collect()
+}
+
+// Build what changed, copy it into the repository directory and add it to
+// the repository database
+pub fn build(declared: &FolderDeclarations) {
+    let Some(local) = &declared.local_repository else {
+        return;
+    };
+    let sources = Path::new(&declared.folder).join(&local.sources);
+    let mut entries: Vec<PathBuf> = match fs::read_dir(&sources) {
+        Ok(entries) => entries.flatten().map(|entry| entry.path()).collect(),
+        Err(e) => {
+            eprintln!("{} Failed to read {}: {}", RED_CROSS, sources.display(), e);
+            return;
+        }
+    };
+    entries.sort();
+    if !create_directory(&local.directory) {
+        eprintln!("{} Failed to create {}", RED_CROSS, local.directory);
+        return;
+    }
+
+    let database_exists = local.database().exists();
+    let mut packages = Vec::new();
+    for entry in &entries {
+        if entry.join("PKGBUILD").is_file() {
+            packages.extend(build_package(entry, local));
+        } else if is_package_file(entry) {
+            let present = entry
+                .file_name()
+                .is_some_and(|name| Path::new(&local.directory).join(name).exists());
+            if !present {
+                packages.push(entry.clone());
+            }
+        }
+    }
+    if packages.is_empty() && database_exists {
+        return;
+    }
+
+    let quoted: Vec<String> = packages
+        .iter()
+        .map(|package| shell_quote(&package.to_string_lossy()))
+        .collect();
+    if !packages.is_empty()
+        && !run_command(
+            &format!("cp -f -- {} {}", quoted.join(" "), shell_quote(&local.directory)),
+            true,
+        )
+    {
+        return;
+    }
+
+    // A missing database is rebuilt from every package in the directory
+    let added: Vec<PathBuf> = if database_exists {
+        packages
+            .iter()
+            .filter_map(|package| package.file_name())
+            .map(|name| Path::new(&local.directory).join(name))
+            .collect()
+    } else {
+        fs::read_dir(&local.directory)
+            .map(|entries| {
+                entries
+                    .flatten()
+                    .map(|entry| entry.path())
+                    .filter(|path| is_package_file(path))
+                    .collect()
+            })
+            .unwrap_or_default()
+    };
+    if added.is_empty() {
+        println!("{} Local repository {} has no packages yet", YELLOW_WARNING, local.name);
+        return;
+    }
+    let added: Vec<String> = added
+        .iter()
+        .map(|package| shell_quote(&package.to_string_lossy()))
+        .collect();
+    let command = format!(
+        "repo-add --remove {} {}",
+        shell_quote(&local.database().to_string_lossy()),
+        added.join(" ")
+    );
+    if run_command(&command, true) {
+        println!(
+            "{} Local repository {} has {} new packages",
+            GREEN_CHECK,
+            local.name,
+            packages.len()
+        );
+    }
+}
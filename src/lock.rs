@@ -0,0 +1,152 @@
+// Exclusive run lock kept in the system directory, and a guard against
+// starting while pacman's own database lock is held
+use crate::snapshot::shell_quote;
+use crate::{
+    BLUE_GEAR, GREEN_CHECK, RED_CROSS, YELLOW_WARNING, ensure_system_directory, paths,
+    privilege,
+};
+use std::fs::{self, File, OpenOptions, TryLockError};
+use std::io::Write;
+use std::path::Path;
+use std::process::{Stdio, exit};
+use std::sync::OnceLock;
+use std::thread;
+use std::time::{Duration, Instant};
+
+const DEFAULT_TIMEOUT: u64 = 60;
+
+static LOCK_TIMEOUT: OnceLock<u64> = OnceLock::new();
+
+pub fn set_timeout(seconds: u64) {
+    let _ = LOCK_TIMEOUT.set(seconds);
+}
+
+fn timeout() -> Duration {
+    Duration::from_secs(*LOCK_TIMEOUT.get().unwrap_or(&DEFAULT_TIMEOUT))
+}
+
+fn lock_file() -> String {
+    paths::state_file("run.lock")
+}
+
+// flock(2) on the open lock file, held for the lifetime of a run. The kernel
+// releases it when the process ends, however it ends, so it is never stale.
+// The file itself stays in place.
+pub struct RunLock {
+    _file: File,
+}
+
+// The state directory belongs to root, flock also works on a read-only
+// descriptor
+fn open_lock(path: &str) -> Option<File> {
+    let writable = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(path);
+    if let Ok(file) = writable {
+        return Some(file);
+    }
+    if !Path::new(path).exists() {
+        let created = privilege::command("touch")
+            .args(["--", path])
+            .status()
+            .is_ok_and(|status| status.success());
+        if !created {
+            return None;
+        }
+    }
+    File::open(path).ok()
+}
+
+// The PID is only shown to runs waiting for this one
+fn record_holder(file: &mut File, path: &str) {
+    let pid = std::process::id();
+    if file.set_len(0).is_ok() && writeln!(file, "{}", pid).is_ok() {
+        return;
+    }
+    let _ = privilege::command("sh")
+        .args(["-c", &format!("echo {} > {}", pid, shell_quote(path))])
+        .stderr(Stdio::null())
+        .status();
+}
+
+fn read_holder(path: &str) -> String {
+    let holder = fs::read_to_string(path).unwrap_or_default().trim().to_string();
+    if holder.is_empty() {
+        "unknown".to_string()
+    } else {
+        holder
+    }
+}
+
+pub fn acquire() -> RunLock {
+    if !Path::new(paths::state_directory()).is_dir() {
+        ensure_system_directory();
+    }
+
+    let path = lock_file();
+    let Some(mut file) = open_lock(&path) else {
+        eprintln!("{} Failed to open lock file {}", RED_CROSS, path);
+        exit(1);
+    };
+    let started = Instant::now();
+    let mut announced = false;
+
+    loop {
+        match file.try_lock() {
+            Ok(()) => {
+                record_holder(&mut file, &path);
+                return RunLock { _file: file };
+            }
+            Err(TryLockError::WouldBlock) => {}
+            Err(TryLockError::Error(e)) => {
+                eprintln!("{} Failed to lock {}: {}", RED_CROSS, path, e);
+                exit(1);
+            }
+        }
+
+        let holder = read_holder(&path);
+        if started.elapsed() >= timeout() {
+            eprintln!(
+                "{} Another run (PID {}) holds {}, giving up after {}s",
+                RED_CROSS,
+                holder,
+                path,
+                timeout().as_secs()
+            );
+            exit(1);
+        }
+        if !announced {
+            println!("{} Waiting for another run (PID {}) to finish", BLUE_GEAR, holder);
+            announced = true;
+        }
+        thread::sleep(Duration::from_secs(1));
+    }
+}
+
+pub fn wait_for_pacman() {
+    let db_lock = format!("{}/db.lck", paths::dbpath());
+    if !Path::new(&db_lock).exists() {
+        return;
+    }
+
+    println!(
+        "{} Pacman database is locked, waiting up to {}s",
+        YELLOW_WARNING,
+        timeout().as_secs()
+    );
+    let started = Instant::now();
+    while Path::new(&db_lock).exists() {
+        if started.elapsed() >= timeout() {
+            eprintln!(
+                "{} {} is still present. If no pacman is running, remove it and try again",
+                RED_CROSS, db_lock
+            );
+            exit(1);
+        }
+        thread::sleep(Duration::from_secs(1));
+    }
+    println!("{} Pacman database lock released", GREEN_CHECK);
+}
@@ -0,0 +1,293 @@
+// novarch.lock: exact versions of every declared package and its dependencies
+use crate::journal::{self, Operation};
+use crate::provenance::{self, Source};
+use crate::{
+    BLUE_GEAR, GREEN_CHECK, RED_CROSS, YELLOW_WARNING, ask_confirmation, cache, database,
+    get_system, history, is_offline, load_config, paths, run_command, save_systemfile, timestamp,
+    write_privileged_file,
+};
+use alpm::Db;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::PathBuf;
+use std::process::exit;
+
+const LOCK_FILE_NAME: &str = "novarch.lock";
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct LockedPackage {
+    pub name: String,
+    pub version: String,
+    pub arch: String,
+    // Sync repository the build came from, none for AUR or local builds
+    pub repo: Option<String>,
+    pub declared: bool,
+    // Declared names this package was locked for, a group or virtual name
+    // differs from the package name and may not resolve on a fresh host
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub declared_as: Vec<String>,
+}
+
+impl LockedPackage {
+    // Lockfiles written before declared_as only know the package name
+    fn locks(&self, name: &str) -> bool {
+        self.declared_as.iter().any(|declared| declared == name)
+            || (self.declared && self.declared_as.is_empty() && self.name == name)
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Lockfile {
+    pub generated: u64,
+    pub packages: Vec<LockedPackage>,
+}
+
+fn lock_path(folder: &str) -> PathBuf {
+    PathBuf::from(folder).join(LOCK_FILE_NAME)
+}
+
+pub fn read_lockfile(folder: &str) -> Result<Lockfile, Box<dyn std::error::Error>> {
+    let file = File::open(lock_path(folder))?;
+    let lockfile = serde_yaml_ng::from_reader(BufReader::new(file))?;
+    Ok(lockfile)
+}
+
+fn declared_names() -> Vec<String> {
+    match get_system() {
+        Ok((_installed, selected, _existing)) => selected,
+        Err(e) => {
+            eprintln!("{} Error reading packages :: {}", RED_CROSS, e);
+            exit(1);
+        }
+    }
+}
+
+// Declared names paired with what they resolve to, groups expanded to their
+// installed members
+fn declared_packages(localdb: &Db) -> Vec<(String, String)> {
+    let mut declared = Vec::new();
+    for name in declared_names() {
+        match localdb.group(name.as_str()) {
+            Ok(group) => declared.extend(
+                group
+                    .packages()
+                    .iter()
+                    .map(|pkg| (pkg.name().to_string(), name.clone())),
+            ),
+            Err(_) => declared.push((name.clone(), name)),
+        }
+    }
+    declared
+}
+
+pub fn lock() {
+    let alpm = database::open_with_syncdbs();
+    let localdb = alpm.localdb();
+
+    let mut locked: BTreeMap<String, LockedPackage> = BTreeMap::new();
+    let mut missing = Vec::new();
+    let mut queue: Vec<(String, Option<String>)> = declared_packages(localdb)
+        .into_iter()
+        .map(|(target, name)| (target, Some(name)))
+        .collect();
+
+    while let Some((target, declared_as)) = queue.pop() {
+        let pkg = match localdb.pkg(target.as_str()) {
+            Ok(pkg) => pkg,
+            Err(_) => match localdb.pkgs().find_satisfier(target.as_str()) {
+                Some(pkg) => pkg,
+                None => {
+                    missing.push(target);
+                    continue;
+                }
+            },
+        };
+
+        if let Some(entry) = locked.get_mut(pkg.name()) {
+            if let Some(name) = declared_as
+                && !entry.declared_as.contains(&name)
+            {
+                entry.declared = true;
+                entry.declared_as.push(name);
+            }
+            continue;
+        }
+
+        locked.insert(
+            pkg.name().to_string(),
+            LockedPackage {
+                name: pkg.name().to_string(),
+                version: pkg.version().to_string(),
+                arch: pkg.arch().unwrap_or("any").to_string(),
+                repo: database::repository_of(&alpm, pkg),
+                declared: declared_as.is_some(),
+                declared_as: declared_as.into_iter().collect(),
+            },
+        );
+        queue.extend(pkg.depends().iter().map(|dep| (dep.to_string(), None)));
+    }
+
+    if !missing.is_empty() {
+        eprintln!(
+            "{} Not installed, run install before locking :\n{:?}",
+            RED_CROSS, missing
+        );
+        exit(1);
+    }
+
+    let lockfile = Lockfile {
+        generated: timestamp::now(),
+        packages: locked.into_values().collect(),
+    };
+
+    let config = load_config();
+    let path = lock_path(&config.folder);
+    let result = serde_yaml_ng::to_string(&lockfile)
+        .map_err(|e| e.to_string())
+        .and_then(|content| {
+            write_privileged_file(&path.to_string_lossy(), &content).map_err(|e| e.to_string())
+        });
+
+    match result {
+        Ok(_) => println!(
+            "{} Locked {} packages in {}",
+            GREEN_CHECK,
+            lockfile.packages.len(),
+            path.display()
+        ),
+        Err(e) => {
+            eprintln!("{} Failed to write {}: {}", RED_CROSS, path.display(), e);
+            exit(1);
+        }
+    }
+}
+
+// Install exactly the locked versions. Nothing is synced or upgraded, and a
+// declaration that is not in the lockfile stops the run.
+pub fn install_locked() {
+    let config = load_config();
+    let lockfile = read_lockfile(&config.folder).unwrap_or_else(|e| {
+        eprintln!(
+            "{} Failed to read {}: {}",
+            RED_CROSS,
+            lock_path(&config.folder).display(),
+            e
+        );
+        exit(1);
+    });
+
+    let alpm = database::open_with_syncdbs();
+    let localdb = alpm.localdb();
+
+    // Checked against the declared names, a group or virtual name does not
+    // resolve locally before its packages are installed
+    let unlocked: Vec<String> = declared_names()
+        .into_iter()
+        .filter(|name| !lockfile.packages.iter().any(|locked| locked.locks(name)))
+        .collect();
+    if !unlocked.is_empty() {
+        eprintln!(
+            "{} Declared but not locked, run lock first :\n{:?}",
+            RED_CROSS, unlocked
+        );
+        exit(1);
+    }
+
+    let drifted: Vec<&LockedPackage> = lockfile
+        .packages
+        .iter()
+        .filter(|locked| {
+            localdb
+                .pkg(locked.name.as_str())
+                .map_or(true, |pkg| pkg.version().as_str() != locked.version)
+        })
+        .collect();
+
+    if drifted.is_empty() {
+        println!("{} System matches {}", GREEN_CHECK, LOCK_FILE_NAME);
+        return;
+    }
+
+    println!("Packages to install from lockfile :");
+    for locked in &drifted {
+        let installed = localdb
+            .pkg(locked.name.as_str())
+            .map_or("not installed".to_string(), |pkg| pkg.version().to_string());
+        println!("  {} {} -> {}", locked.name, installed, locked.version);
+    }
+
+    let mirror = config.archive_mirror().to_string();
+    let mut unavailable = Vec::new();
+    let mut explicit = Vec::new();
+    let mut dependencies = Vec::new();
+    for locked in &drifted {
+        let cached = cache::find_local(&locked.name, &locked.version, &locked.arch);
+        let source = match (cached, &locked.repo) {
+            (Some(path), _) => path.display().to_string(),
+            (None, Some(_)) if !is_offline() => {
+                cache::archive_url(&mirror, &locked.name, &locked.version, &locked.arch)
+            }
+            (None, _) => {
+                unavailable.push(format!("{} {}", locked.name, locked.version));
+                continue;
+            }
+        };
+        if locked.declared {
+            explicit.push(format!("'{}'", source));
+        } else {
+            dependencies.push(format!("'{}'", source));
+        }
+    }
+
+    if !unavailable.is_empty() {
+        eprintln!(
+            "{} Not in the package cache and not downloadable :\n{:?}",
+            RED_CROSS, unavailable
+        );
+        exit(1);
+    }
+
+    if !ask_confirmation("Do you want to proceed installing locked versions [Y/n] : ") {
+        return;
+    }
+
+    let declared: Vec<String> = drifted
+        .iter()
+        .filter(|locked| locked.declared)
+        .map(|locked| locked.name.clone())
+        .collect();
+    journal::begin(Operation::Install, &declared, Some(Source::Folder));
+    let started = timestamp::now();
+
+    if !dependencies.is_empty() {
+        println!("{} Installing locked dependencies", BLUE_GEAR);
+        run_command(
+            &format!(
+                "pacman{} -U --noconfirm --asdeps -- {}",
+                paths::root_args(),
+                dependencies.join(" ")
+            ),
+            true,
+        );
+    }
+    if !explicit.is_empty() {
+        println!("{} Installing locked packages", BLUE_GEAR);
+        run_command(
+            &format!("pacman{} -U --noconfirm -- {}", paths::root_args(), explicit.join(" ")),
+            true,
+        );
+    }
+
+    history::record("install", "lockfile", started);
+
+    let mut config = load_config();
+    provenance::track(&mut config, &declared, Source::Folder);
+    match save_systemfile(&config) {
+        Ok(_) => journal::complete(),
+        Err(e) => eprintln!("{} Failed to save systemfile: {}", YELLOW_WARNING, e),
+    }
+
+    println!("{} System matches {}", GREEN_CHECK, LOCK_FILE_NAME);
+}
@@ -1,14 +1,39 @@
 // Declarative package manager for arch linux
-use alpm::Alpm;
+mod atomic;
+mod cache;
+mod database;
+mod declarations;
+mod failure;
+mod generations;
+mod history;
+mod hooks;
+mod journal;
+mod keyring;
+mod local_repository;
+mod lock;
+mod lockfile;
+mod mirrors;
+mod offline;
+mod pacman_conf;
+mod paths;
+mod privilege;
+mod provenance;
+mod repositories;
+mod repair;
+mod rollback;
+mod snapshot;
+mod timestamp;
+
 use alpm::PackageReason;
 use clap::{Parser, Subcommand};
 use const_format::concatcp;
 use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
 use std::env;
 use std::fs;
 use std::fs::File;
 use std::fs::OpenOptions;
-use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::io::{self, BufReader, IsTerminal, Read, Write};
 use std::path::Path;
 use std::path::PathBuf;
 use std::process::{Command, Stdio, exit};
@@ -17,11 +42,19 @@ use std::thread;
 use std::time::Duration;
 use rustyline::completion::FilenameCompleter;
 use rustyline::Editor;
+use failure::{FailureClass, FailurePolicies, Policy};
+use declarations::{FolderDeclarations, PackageEntry, PackageFile};
+use journal::Operation;
+use privilege::Escalation;
+use provenance::{Provenance, Source};
+use repositories::Repository;
+use snapshot::SnapshotBackend;
 
 
 static ORIGINAL_USER: OnceLock<String> = OnceLock::new();
-const SYSTEM_DIRECTORY: &str = "/var/lib/novarch";
-const SYSTEM_FILE: &str = "/var/lib/novarch/system.yaml";
+static NON_INTERACTIVE: OnceLock<bool> = OnceLock::new();
+static OFFLINE: OnceLock<bool> = OnceLock::new();
+const DEFAULT_ARCHIVE_MIRROR: &str = "https://archive.archlinux.org";
 
 // ANSI color codes
 const RESET: &str = "\x1b[0m";
@@ -31,9 +64,19 @@ const BLUE_GEAR: &str = concatcp!("\x1b[94m", "⚙", RESET);
 const GREEN_CHECK: &str = concatcp!("\x1b[92m", "✓", RESET);
 
 fn get_original_user() -> Result<(), String> {
-    let user = env::var("USER")
-        .or_else(|_| env::var("LOGNAME"))
-        .map_err(|_| "Could not determine current user".to_string())?;
+    // When started through an escalation tool, the invoking user is the one
+    // that owns the packages folder and runs paru
+    let invoking_user = if privilege::is_root() {
+        env::var("SUDO_USER").or_else(|_| env::var("DOAS_USER")).ok()
+    } else {
+        None
+    };
+    let user = match invoking_user {
+        Some(user) => user,
+        None => env::var("USER")
+            .or_else(|_| env::var("LOGNAME"))
+            .map_err(|_| "Could not determine current user".to_string())?,
+    };
 
     ORIGINAL_USER
         .set(user.clone())
@@ -41,7 +84,26 @@ fn get_original_user() -> Result<(), String> {
     Ok(())
 }
 
+fn is_non_interactive() -> bool {
+    *NON_INTERACTIVE.get().unwrap_or(&false)
+}
+
+// No mirror or keyserver is reachable, only local files are used
+fn is_offline() -> bool {
+    *OFFLINE.get().unwrap_or(&false)
+}
+
+// Appended to pacman/paru invocations that would otherwise prompt
+fn noconfirm_flag() -> &'static str {
+    if is_non_interactive() { " --noconfirm" } else { "" }
+}
+
 fn ask_confirmation(message: &str) -> bool {
+    if is_non_interactive() {
+        println!("{}y (non-interactive)", message);
+        return true;
+    }
+
     print!("{}", message);
     io::stdout().flush().expect("Failed to flush stdout");
     
@@ -55,168 +117,290 @@ fn ask_confirmation(message: &str) -> bool {
 }
 
 
-fn is_network_or_download_error(error_output: &str) -> bool {
-    let error_lower = error_output.to_lowercase();
-    
-    let network_patterns = [
-        "failed retrieving file",
-        "failed to download",
-        "download failed",
-        "connection timed out",
-        "connection refused",
-        "could not resolve host",
-        "temporary failure in name resolution",
-        "network is unreachable",
-        "curl error",
-        "timeout",
-        "ssl",
-        "tls",
-        "certificate",
-        "failed to retrieve",
-        "error: target not found",  // Sometimes network related
-        "could not connect",
-        "no route to host",
-        "http error 404",
-        "http error 503",
-        "http error 502",
-    ];
-    
-    network_patterns.iter().any(|pattern| error_lower.contains(pattern))
-}
-
-fn run_command(command: &str, needs_sudo: bool) -> bool {
-    let final_command = if needs_sudo {
-        format!("sudo {}", command)
-    } else {
-        command.to_string()
-    };
-
-    let max_attempts = 3;
-    let mut attempts = 0;
-
-    let out = Command::new("sh")
+fn run_capturing_stderr(command: &str) -> io::Result<(bool, String)> {
+    let mut child = Command::new("sh")
         .arg("-c")
-        .arg(&final_command)
+        .arg(command)
         .stdout(Stdio::inherit())
         .stdin(Stdio::inherit())
-        .stderr(Stdio::inherit())
-        .status();
-
-    match out {
-        Ok(status) => {
-            if status.success() {
-                return true;
-            }else {
-                if !ask_confirmation("Error occured do you want to retry [Y/n] : "){
-                    std::process::exit(1);
+        .stderr(Stdio::piped())
+        .spawn()?;
+
+    // Forward stderr as it arrives so prompts and progress stay visible
+    let mut stderr = child.stderr.take().expect("stderr is piped");
+    let reader = thread::spawn(move || {
+        let mut captured = Vec::new();
+        let mut buffer = [0u8; 4096];
+        loop {
+            match stderr.read(&mut buffer) {
+                Ok(0) | Err(_) => break,
+                Ok(n) => {
+                    let _ = io::stderr().write_all(&buffer[..n]);
+                    captured.extend_from_slice(&buffer[..n]);
                 }
             }
         }
+        captured
+    });
+
+    let status = child.wait()?;
+    let captured = reader.join().unwrap_or_default();
+    Ok((status.success(), String::from_utf8_lossy(&captured).into_owned()))
+}
+
+fn refresh_keyring() -> bool {
+    println!("{} Refreshing pacman keyring", BLUE_GEAR);
+    ["pacman-key --init", "pacman-key --populate archlinux", "pacman -Syu --noconfirm archlinux-keyring"]
+        .iter()
+        .all(|command| {
+            Command::new("sh")
+                .arg("-c")
+                .arg(format!("{} {}", privilege::prefix(), command))
+                .status()
+                .map(|status| status.success())
+                .unwrap_or(false)
+        })
+}
+
+fn with_prefix(command: &str, privileged: bool) -> String {
+    let prefix = if privileged {
+        privilege::prefix()
+    } else {
+        privilege::user_prefix()
+    };
+    if prefix.is_empty() {
+        command.to_string()
+    } else {
+        format!("{} {}", prefix, command)
+    }
+}
+
+// Single attempt for steps whose failure must not stop the run, unlike run_command
+fn try_command(command: &str, privileged: bool) -> bool {
+    let final_command = with_prefix(command, privileged);
+    lock::wait_for_pacman();
+    match run_capturing_stderr(&final_command) {
+        Ok((success, _)) => success,
         Err(e) => {
-            eprintln!("{} Failed to run command {}\n Error:{}", RED_CROSS, final_command, e)
+            eprintln!("{} Command execution failed: {}\n {}", YELLOW_WARNING, final_command, e);
+            false
         }
     }
+}
+
+fn run_command(command: &str, privileged: bool) -> bool {
+    let final_command = with_prefix(command, privileged);
+
+    let policies = read_system_file()
+        .map(|config| config.failure_policies)
+        .unwrap_or_default();
+    // Counted per failure class, so a retry budget is not spent by other failures
+    let mut attempts_by_class: HashMap<FailureClass, u32> = HashMap::new();
+    let mut keyring_refreshed = false;
+
     loop {
-        attempts += 1;
-
-        let result = Command::new("sh")
-            .arg("-c")
-            .arg(&final_command)
-            .stdout(Stdio::inherit())
-            .stdin(Stdio::inherit())
-            .stderr(Stdio::piped())
-            .output();
-
-        match result {
-            Ok(output) => {
-                let stderr_output = String::from_utf8_lossy(&output.stderr);
-                
-                if output.status.success() {
-                    return true;
-                } else {
-                    eprint!("{}", stderr_output);
-                    
-                    if is_network_or_download_error(&stderr_output) {
-                        eprintln!(
-                            "\n{} Network/Download error detected (attempt {})",
-                            YELLOW_WARNING, attempts
-                        );
-                        if attempts >= max_attempts {
-                            eprintln!(
-                                "{} Installation failed after {} attempts due to network issues",
-                                RED_CROSS, max_attempts
-                            );
-                            std::process::exit(1);
-                        } else {
-                            let duration = attempts*5;
-                            thread::sleep(Duration::from_secs(duration));
-                        }
-                    } else {
-                        if attempts >= max_attempts {
-                            std::process::exit(1)
-                        }else {
-                            if !ask_confirmation("Error occured do you want to retry [Y/n] : "){
-                                std::process::exit(1);
-                            }
-                        }
-                    }
-                }
-            }
+        lock::wait_for_pacman();
+
+        let stderr_output = match run_capturing_stderr(&final_command) {
+            Ok((true, _)) => return true,
+            Ok((false, stderr_output)) => stderr_output,
             Err(e) => {
                 eprintln!("{} Command execution failed: {}\n {}", RED_CROSS, final_command, e);
-                std::process::exit(1);
+                exit(1);
+            }
+        };
+
+        let class = failure::classify(&stderr_output);
+        let policy = policies.for_class(class);
+        let attempts = {
+            let count = attempts_by_class.entry(class).or_insert(0);
+            *count += 1;
+            *count
+        };
+        eprintln!(
+            "\n{} {} error detected (attempt {}), policy: {}",
+            YELLOW_WARNING, class, attempts, policy
+        );
+
+        match policy {
+            Policy::Retry { attempts: max_attempts, backoff } => {
+                if attempts >= *max_attempts {
+                    eprintln!(
+                        "{} Command failed after {} attempts: {}",
+                        RED_CROSS, attempts, final_command
+                    );
+                    exit(1);
+                }
+                let delay = failure::retry_delay(*backoff, attempts);
+                println!("{} Retrying in {}s", BLUE_GEAR, delay);
+                thread::sleep(Duration::from_secs(delay));
+            }
+            Policy::RefreshKeyring => {
+                if keyring_refreshed || !refresh_keyring() {
+                    eprintln!("{} Keyring refresh did not resolve the failure", RED_CROSS);
+                    exit(1);
+                }
+                keyring_refreshed = true;
+            }
+            Policy::Abort => {
+                eprintln!("{} Aborting: {}", RED_CROSS, final_command);
+                exit(1);
+            }
+            Policy::Ask => {
+                if is_non_interactive() {
+                    eprintln!("{} Not retrying in non-interactive mode", RED_CROSS);
+                    exit(1);
+                }
+                if !ask_confirmation("Error occured do you want to retry [Y/n] : ") {
+                    exit(1);
+                }
             }
         }
     }
 }
 
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Default, Deserialize, Serialize)]
 struct Config {
     folder: String,
     packages: Vec<String>,
+    #[serde(default)]
+    failure_policies: FailurePolicies,
+    // Where exact versions missing from the package cache are fetched from
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    archive_mirror: Option<String>,
+    #[serde(default)]
+    snapshots: SnapshotBackend,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    provenance: BTreeMap<String, Provenance>,
+    // Repositories this tool configured in pacman.conf, removed once undeclared
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    managed_repositories: Vec<String>,
+    // Packages installed from the bootstrap entries of a managed repository
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    bootstrap_packages: BTreeMap<String, Vec<String>>,
+    // Whether init already offered the built-in repositories
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    builtin_repositories_offered: bool,
+    // Hook files this tool installed, removed once undeclared
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    managed_hooks: Vec<String>,
 }
 
-fn save_systemfile(config: &Config) -> Result<(), Box<dyn std::error::Error>> {
-    let yaml_content = serde_yaml_ng::to_string(config)?;
+impl Config {
+    fn archive_mirror(&self) -> &str {
+        self.archive_mirror.as_deref().unwrap_or(DEFAULT_ARCHIVE_MIRROR)
+    }
+}
 
-    let mut child = Command::new("sudo")
-        .args(&["tee", SYSTEM_FILE])
+fn append_privileged(path: &str, content: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let mut child = privilege::command("tee")
+        .args(["-a", path])
         .stdin(Stdio::piped())
         .stdout(Stdio::null())
         .spawn()?;
 
     if let Some(mut stdin) = child.stdin.take() {
-        stdin.write_all(yaml_content.as_bytes())?;
+        stdin.write_all(content.as_bytes())?;
     }
 
     let status = child.wait()?;
     if !status.success() {
-        return Err("Failed to write system file".into());
+        return Err(format!("Failed to write {}", path).into());
     }
 
     Ok(())
 }
 
-fn ensure_system_directory() {
-    let result = Command::new("sudo")
-        .args(&["mkdir", "-p", SYSTEM_DIRECTORY])
-        .status();
+// Same steps as atomic::write, run as root
+const ATOMIC_WRITE_SCRIPT: &str = r#"set -e
+tee "$2" >/dev/null
+sync "$2"
+if [ -e "$1" ]; then
+    chmod --reference="$1" "$2"
+    chown --reference="$1" "$2"
+    if [ "$4" = 1 ] && [ -s "$1" ]; then cp -p "$1" "$3"; fi
+fi
+mv "$2" "$1"
+sync "$(dirname "$1")""#;
+
+fn atomic_write_privileged(path: &str, content: &str, backup: bool) -> Result<(), Box<dyn std::error::Error>> {
+    let mut child = privilege::command("sh")
+        .args(["-c", ATOMIC_WRITE_SCRIPT, "sh", path])
+        .arg(atomic::temp_path(path))
+        .arg(atomic::backup_path(path))
+        .arg(if backup { "1" } else { "0" })
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .spawn()?;
+
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(content.as_bytes())?;
+    }
+
+    let status = child.wait()?;
+    if !status.success() {
+        return Err(format!("Failed to write {}", path).into());
+    }
+
+    Ok(())
+}
+
+fn replace_file(path: &str, content: &str, backup: bool) -> Result<(), Box<dyn std::error::Error>> {
+    match atomic::write(path, content, backup) {
+        Ok(_) => Ok(()),
+        Err(e) if e.kind() == io::ErrorKind::PermissionDenied => atomic_write_privileged(path, content, backup),
+        Err(e) => Err(e.into()),
+    }
+}
 
+// Writes directly when we may, e.g. a state directory in a temp dir, and
+// through privilege escalation otherwise. The file is replaced atomically and
+// its previous contents kept as a .bak
+fn write_privileged_file(path: &str, content: &str) -> Result<(), Box<dyn std::error::Error>> {
+    replace_file(path, content, true)
+}
+
+fn append_privileged_file(path: &str, content: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let result = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .and_then(|mut file| file.write_all(content.as_bytes()));
     match result {
-        Ok(status) => {
-            if !status.success() {
-                eprintln!("{} Failed to create system directory", RED_CROSS);
-            }
-        }
+        Ok(_) => Ok(()),
+        Err(e) if e.kind() == io::ErrorKind::PermissionDenied => append_privileged(path, content),
+        Err(e) => Err(e.into()),
+    }
+}
+
+fn save_systemfile(config: &Config) -> Result<(), Box<dyn std::error::Error>> {
+    let yaml_content = serde_yaml_ng::to_string(config)?;
+    write_privileged_file(paths::system_file(), &yaml_content)
+}
+
+fn create_directory(path: &str) -> bool {
+    if fs::create_dir_all(path).is_ok() {
+        return true;
+    }
+
+    match privilege::command("mkdir").args(["-p", path]).status() {
+        Ok(status) => status.success(),
         Err(e) => {
             eprintln!("{} Failed to execute mkdir: {}", RED_CROSS, e);
+            false
         }
     }
 }
 
+fn ensure_system_directory() {
+    if !create_directory(paths::state_directory()) {
+        eprintln!("{} Failed to create system directory", RED_CROSS);
+    }
+}
+
 fn read_system_file() -> Result<Config, Box<dyn std::error::Error>> {
-    let file = File::open(SYSTEM_FILE)?;
+    let file = File::open(paths::system_file())?;
     let reader = BufReader::new(file);
     let config: Config = serde_yaml_ng::from_reader(reader)?;
     Ok( config )
@@ -225,8 +409,33 @@ fn read_system_file() -> Result<Config, Box<dyn std::error::Error>> {
 fn load_config() -> Config {
     read_system_file().unwrap_or_else(|e| {
         eprintln!("{} Failed to read system file: {}", RED_CROSS, e);
-        exit(1);
-    })  
+        restore_system_file().unwrap_or_else(|| exit(1))
+    })
+}
+
+// Offer the last good copy when system.yaml is missing or corrupt
+fn restore_system_file() -> Option<Config> {
+    let backup = atomic::backup_path(paths::system_file());
+    let content = fs::read_to_string(&backup).ok()?;
+    let config: Config = match serde_yaml_ng::from_str(&content) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("{} Backup {} is unusable too: {}", RED_CROSS, backup, e);
+            return None;
+        }
+    };
+
+    println!("{} Last good copy found at {}", YELLOW_WARNING, backup);
+    if !ask_confirmation("Do you want to restore it [Y/n] : ") {
+        return None;
+    }
+    // The corrupt file must not replace the good backup
+    if let Err(e) = replace_file(paths::system_file(), &content, false) {
+        eprintln!("{} Failed to restore system file: {}", RED_CROSS, e);
+        return None;
+    }
+    println!("{} Restored {} from its backup", GREEN_CHECK, paths::system_file());
+    Some(config)
 }
 
 #[derive(rustyline_derive::Helper, rustyline_derive::Completer, 
@@ -237,7 +446,23 @@ struct PathHelper {
     completer: FilenameCompleter,
 }
 
-fn get_packages_folder() -> String {
+fn get_packages_folder(existing: Option<&str>) -> String {
+    if is_non_interactive() {
+        return match existing {
+            Some(folder) if !folder.is_empty() => {
+                println!("{} Using packages folder {}", BLUE_GEAR, folder);
+                folder.to_string()
+            }
+            _ => {
+                eprintln!(
+                    "{} No packages folder configured, pass --folder in non-interactive mode",
+                    RED_CROSS
+                );
+                exit(1);
+            }
+        };
+    }
+
     let helper = PathHelper {
         completer: FilenameCompleter::new(),
     };
@@ -264,12 +489,15 @@ fn check_package_installed(package: &str) -> bool {
         .unwrap_or(false)
 }
 
-fn setup_check() {
+fn setup_check(folder_override: Option<&str>) {
     ensure_system_directory();
 
-    if Path::new(SYSTEM_FILE).exists() {
+    if Path::new(paths::system_file()).exists() {
         let mut config = load_config();
-        let folder = get_packages_folder();
+        let folder = match folder_override {
+            Some(folder) => folder.to_string(),
+            None => get_packages_folder(Some(&config.folder)),
+        };
         if Path::new(&folder).is_dir() {
             config.folder = folder;
             if let Err(e) = save_systemfile(&config) {
@@ -281,11 +509,11 @@ fn setup_check() {
             "{} System file does not exist, starting fresh...",
             YELLOW_WARNING
         );
-        let folder = get_packages_folder();
-        let mut new_config = Config {
-            folder: String::new(),
-            packages: Vec::new(),
+        let folder = match folder_override {
+            Some(folder) => folder.to_string(),
+            None => get_packages_folder(None),
         };
+        let mut new_config = Config::default();
         if Path::new(&folder).is_dir() {
             new_config.folder = folder;
             if let Err(e) = save_systemfile(&new_config) {
@@ -295,171 +523,180 @@ fn setup_check() {
     }
 }
 
-fn update_system() {
-    if !check_package_installed("reflector") {
-        println!("{} Reflector not installed, installing now", YELLOW_WARNING);
-        run_command("pacman -S --noconfirm reflector", true);
+// The sections of the packages folder besides packages, read once per run.
+// When they fail to parse, pacman.conf, keys, hooks and mirrors are left alone.
+fn read_folder_declarations() -> Option<FolderDeclarations> {
+    match FolderDeclarations::read(&load_config().folder) {
+        Ok(declared) => Some(declared),
+        Err(e) => {
+            eprintln!("{} Error reading the packages folder :: {}", RED_CROSS, e);
+            None
+        }
+    }
+}
+
+fn converge_folder(declared: &FolderDeclarations, initial: &[Repository]) {
+    local_repository::build(declared);
+    keyring::converge(declared);
+    pacman_conf::converge(declared, initial);
+    hooks::converge(declared);
+}
+
+fn update_system(declared: Option<&FolderDeclarations>) {
+    if is_offline() {
+        println!("{} Offline, skipping the mirrorlist and the system upgrade", YELLOW_WARNING);
+        return;
     }
     println!("{} Starting update", BLUE_GEAR);
-    run_command(
-        "reflector --latest 10 --protocol https --sort rate --save /etc/pacman.d/mirrorlist 2>/dev/null",
-        true,
-    );
+    if let Some(declared) = declared {
+        mirrors::refresh(declared);
+    }
 
     if !check_package_installed("paru") {
         println!("{} Paru not installed, installing now", YELLOW_WARNING);
         run_command("pacman -S --noconfirm paru", true);
     }
-    run_command("paru -Syu --noconfirm", false);
-}
-
-fn chaotic_aur_setup() {
-    let multilib_enabled = match OpenOptions::new().read(true).open("/etc/pacman.conf") {
-        Ok(file) => BufReader::new(file)
-            .lines()
-            .map(|line| line.unwrap_or_default())
-            .any(|line| line == "[multilib]"),
-        Err(_) => false,
-    };
-
-    if !multilib_enabled {
-        let multilib_content = "[multilib]\\nInclude = /etc/pacman.d/mirrorlist\\n";
-        let result = Command::new("sudo")
-            .arg("sh")
-            .arg("-c")
-            .arg(format!(
-                "echo -e '{}' >> /etc/pacman.conf",
-                multilib_content
-            ))
-            .status();
-
-        match result {
-            Ok(status) => {
-                if !status.success() {
-                    eprintln!("{} Failed to add multilib", RED_CROSS);
-                }
-            }
-            Err(e) => {
-                eprintln!("{} Failed to modify pacman.conf: {}", RED_CROSS, e);
-            }
-        }
-    }
-
-    let chaotic_enabled = match OpenOptions::new().read(true).open("/etc/pacman.conf") {
-        Ok(file) => BufReader::new(file)
-            .lines()
-            .map(|line| line.unwrap_or_default())
-            .any(|line| line == "[chaotic-aur]"),
-        Err(_) => false,
-    };
-
-    if !chaotic_enabled {
-        println!("Configuring Chaotic-AUR");
-        run_command("pacman -Syu", true);
-        run_command("pacman-key --init", true);
-        run_command("pacman -Sy --noconfirm archlinux-keyring", true);
-        run_command(
-            "pacman-key --recv-key 3056513887B78AEB --keyserver keyserver.ubuntu.com",
-            true,
-        );
-        run_command("pacman-key --lsign-key 3056513887B78AEB", true);
-        run_command(
-            "pacman -U --noconfirm 'https://cdn-mirror.chaotic.cx/chaotic-aur/chaotic-keyring.pkg.tar.zst'",
-            true,
-        );
-        run_command(
-            "pacman -U --noconfirm 'https://cdn-mirror.chaotic.cx/chaotic-aur/chaotic-mirrorlist.pkg.tar.zst'",
-            true,
-        );
-
-        let chaotic_content = "[chaotic-aur]\\nInclude = /etc/pacman.d/chaotic-mirrorlist\\n";
-        let result = Command::new("sudo")
-            .arg("sh")
-            .arg("-c")
-            .arg(format!("echo -e '{}' >> /etc/pacman.conf", chaotic_content))
-            .status();
-
-        match result {
-            Ok(status) => {
-                if status.success() {
-                    println!("{} Chaotic-AUR added", GREEN_CHECK);
-                } else {
-                    eprintln!("{} Failed to add chaotic-aur to config", RED_CROSS);
-                }
-            }
-            Err(e) => {
-                eprintln!("{} Failed to modify pacman.conf: {}", RED_CROSS, e);
-            }
-        }
-        run_command("pacman -Syu --noconfirm", true);
+    let started = timestamp::now();
+    if run_command(&format!("paru{} -Syu --noconfirm", paths::root_args()), false) {
+        history::record("upgrade", "system upgrade", started);
     }
 }
 
 fn get_system() -> Result<(Vec<String>, Vec<String>, Vec<String>), Box<dyn std::error::Error>> {
-    let alpm = Alpm::new("/", "/var/lib/pacman").expect("Failed to read Database");
+    let alpm = database::open();
     let db = alpm.localdb();
     let packages_installed: Vec<String> =
          db.pkgs().iter().map(|pkg| pkg.name().to_string()).collect();
     let config = load_config();
     let existing_packages = config.packages;
-    let folder = config.folder;
-
-    let mut all_packages = Vec::new();
-    for entry in fs::read_dir(folder)? {
-        let entry = entry?;
-        let path = entry.path();
-        if path.is_file() {
-            if let Some(filename) = path.file_name() {
-                if let Some(filename_str) = filename.to_str() {
-                    if filename_str.ends_with(".yaml") {
-                        let file = File::open(path)?;
-                        let reader = BufReader::new(file);
-                        let packages: Vec<String> = serde_yaml_ng::from_reader(reader)?;
-                        all_packages.extend(packages)
-                    }
-                }
-            }
-        }
-    }
+    let all_packages = declarations::read_declarations(&config.folder)?
+        .into_iter()
+        .map(|declaration| declaration.name)
+        .collect();
 
     Ok((packages_installed, all_packages, existing_packages))
 }
 
 
-fn install_packages() {
-    let packages_selected;
-    let mut existing_packages;
-    let mut install_command = format!("paru -S --needed --noconfirm -- ");
+struct Plan {
+    install: Vec<String>,
+    remove: Vec<String>,
+    // Installed from another repository than the one the declaration pins
+    repin: Vec<String>,
+}
+
+impl Plan {
+    fn is_empty(&self) -> bool {
+        self.install.is_empty() && self.remove.is_empty() && self.repin.is_empty()
+    }
 
-    match get_system() {
-        Ok((_packages_installed, selected, existing)) => {
-            packages_selected = selected;
-            existing_packages = existing;
+    fn summary(&self) -> String {
+        let names = |packages: &[String]| {
+            let mut shown = packages.iter().take(5).cloned().collect::<Vec<_>>().join(", ");
+            if packages.len() > 5 {
+                shown.push_str(", ...");
+            }
+            shown
+        };
+        let mut parts = Vec::new();
+        if !self.install.is_empty() {
+            parts.push(format!("install {} ({})", self.install.len(), names(&self.install)));
         }
-        Err(e) => {
-            eprintln!("{} Error reading packages :: {}", RED_CROSS, e);
-            return;
+        if !self.remove.is_empty() {
+            parts.push(format!("remove {} ({})", self.remove.len(), names(&self.remove)));
+        }
+        if !self.repin.is_empty() {
+            parts.push(format!("repin {} ({})", self.repin.len(), names(&self.repin)));
+        }
+        if parts.is_empty() {
+            "no package changes".to_string()
+        } else {
+            parts.join(", ")
         }
     }
+}
 
-    let tobe_installed: Vec<String> = packages_selected
-        .into_iter()
+fn plan_changes() -> Result<Plan, Box<dyn std::error::Error>> {
+    let (packages_installed, packages_selected, existing_packages) = get_system()?;
+
+    let install: Vec<String> = packages_selected
+        .iter()
         .filter(|item| !existing_packages.contains(item))
+        .cloned()
+        .collect();
+
+    let mut remove: Vec<String> = existing_packages
+        .into_iter()
+        .filter(|item| !packages_selected.contains(item))
+        .filter(|item| packages_installed.contains(item))
+        .collect();
+
+    let alpm = database::open_with_syncdbs();
+    let db = alpm.localdb();
+
+    remove.retain(|package| match db.pkg(package.to_string()) {
+        Ok(pkg) => pkg.required_by().is_empty(),
+        Err(_) => false,
+    });
+
+    let repin = declarations::read_declarations(&load_config().folder)?
+        .into_iter()
+        .filter(|declaration| !install.contains(&declaration.name))
+        .filter(|declaration| {
+            let (Some(repo), Ok(pkg)) = (&declaration.repo, db.pkg(declaration.name.as_str())) else {
+                return false;
+            };
+            match database::pinned_elsewhere(&alpm, pkg, repo) {
+                Some(elsewhere) => elsewhere,
+                None => {
+                    eprintln!(
+                        "{} {} is pinned to {}, which does not provide it",
+                        YELLOW_WARNING, declaration.name, repo
+                    );
+                    false
+                }
+            }
+        })
+        .map(|declaration| declaration.name)
         .collect();
 
+    Ok(Plan { install, remove, repin })
+}
+
+fn install_targets(packages: &[String]) -> Vec<String> {
+    let declarations = declarations::read_declarations(&load_config().folder).unwrap_or_default();
+    packages
+        .iter()
+        .map(|package| declarations::install_target(&declarations, package))
+        .collect()
+}
+
+fn install_packages(tobe_installed: Vec<String>) {
+    // paru would reach for the AUR, pacman -S without -y only needs the cache
+    let mut install_command = if is_offline() {
+        format!("pacman{} -S --needed --noconfirm -- ", paths::root_args())
+    } else if paths::target_unsynced() {
+        // Like pacstrap, a fresh root syncs its databases once
+        format!("paru{} -Sy --needed --noconfirm -- ", paths::root_args())
+    } else {
+        format!("paru{} -S --needed --noconfirm -- ", paths::root_args())
+    };
+
     if !tobe_installed.is_empty() {
-        install_command.push_str(&tobe_installed.join(" "));
+        install_command.push_str(&install_targets(&tobe_installed).join(" "));
         println!("Packages to install :\n{:?}", (&tobe_installed));
         let confirmation = ask_confirmation("Do you want to proceed installing above packages [Y/n] : ");
         if confirmation {
-            let install_status = run_command(&install_command, false);
+            journal::begin(Operation::Install, &tobe_installed, Some(Source::Folder));
+            let started = timestamp::now();
+            let install_status = run_command(&install_command, is_offline());
             if install_status {
+                history::record("install", "declared in folder", started);
                 println!("{} All packages installed", GREEN_CHECK);
                 let mut config = load_config();
-                existing_packages.extend(tobe_installed);
-                config.packages = existing_packages;
+                provenance::track(&mut config, &tobe_installed, Source::Folder);
                 match save_systemfile(&config) {
-                    Ok(_) => {}
+                    Ok(_) => journal::complete(),
                     Err(_) => eprintln!("Failed to save systemfile"),
                 }
             }
@@ -470,51 +707,28 @@ fn install_packages() {
 }
 
 
-fn remove_packages() {
-    let packages_selected;
-    let packages_installed;
-    let mut existing_packages;
-    let mut remove_command = format!("pacman -Rns --noconfirm ");
-
-    match get_system() {
-        Ok((installed, selected, existing)) => {
-            packages_selected = selected;
-            existing_packages = existing;
-            packages_installed = installed;
-        }
-        Err(e) => {
-            eprintln!("Error reading packages :: {}", e);
-            return;
-        }
-    }
-
-    let mut tobe_removed: Vec<String> = existing_packages
-        .clone()
-        .into_iter()
-        .filter(|item| !packages_selected.contains(item))
-        .filter(|item| packages_installed.contains(item))
-        .collect();
-
-    let alpm = Alpm::new("/", "/var/lib/pacman").expect("Database not found");
-    let db = alpm.localdb();
-
-    tobe_removed.retain(|package| match db.pkg(package.to_string()) {
-        Ok(pkg) => pkg.required_by().is_empty(),
-        Err(_) => false,
-    });
+fn remove_packages(tobe_removed: Vec<String>) {
+    let mut remove_command = format!("pacman{} -Rns --noconfirm ", paths::root_args());
 
     if !tobe_removed.is_empty() {
         remove_command.push_str(&tobe_removed.join(" "));
-        println!("Packages to remove :\n{:?}", (&tobe_removed));
+        let config = load_config();
+        let declarations = declarations::read_declarations(&config.folder).unwrap_or_default();
+        println!("Packages to remove :");
+        for package in &tobe_removed {
+            println!("  {} ({})", package, provenance::describe(&config, &declarations, package));
+        }
         let confirmation = ask_confirmation("Do you want to proceed removing above packages [Y/n] : ");
         if confirmation {
+            journal::begin(Operation::Remove, &tobe_removed, None);
+            let started = timestamp::now();
             let removal_status = run_command(&remove_command, true);
             if removal_status {
-                existing_packages.retain(|item| !tobe_removed.contains(item));
+                history::record("remove", "no longer declared", started);
                 let mut config = load_config();
-                config.packages = existing_packages;
+                provenance::untrack(&mut config, &tobe_removed);
                 match save_systemfile(&config) {
-                    Ok(_) => {}
+                    Ok(_) => journal::complete(),
                     Err(_) => eprintln!("Failed to save systemfile"),
                 }
             }
@@ -531,59 +745,69 @@ fn add_package(packages: &[String]) {
         return;
     }
 
-    let install_command = format!("paru -S --needed -- {}", packages.join(" "));
+    let install_command = format!(
+        "paru{} -S --needed{} -- {}",
+        paths::root_args(),
+        noconfirm_flag(),
+        packages.join(" ")
+    );
 
+    journal::begin(Operation::Install, packages, Some(Source::Add));
+    let started = timestamp::now();
     run_command(&install_command, false);
 
+    // Only record what actually made it onto the system
+    let (packages, failed): (Vec<String>, Vec<String>) = packages
+        .iter()
+        .cloned()
+        .partition(|package| database::is_installed(package));
+    if !failed.is_empty() {
+        eprintln!("{} Packages not installed :\n{:?}", YELLOW_WARNING, failed);
+    }
+    if packages.is_empty() {
+        return;
+    }
     println!("{} Packages installed successfully", GREEN_CHECK);
+    history::record("install", "add", started);
 
     let mut config = load_config();
-    let manual_install_path = PathBuf::from(&config.folder).join("manual-install.yaml");
-    let mut manual_packages: Vec<String> = if manual_install_path.exists() {
-        match File::open(&manual_install_path) {
-            Ok(file) => {
-                let reader = BufReader::new(file);
-                serde_yaml_ng::from_reader(reader).unwrap_or_else(|_| Vec::new())
-            }
-            Err(_) => Vec::new(),
-        }
-    } else {
-        Vec::new()
-    };
-
-    for package in packages {
-        if !config.packages.contains(package) {
-            config.packages.push(package.clone());
-            manual_packages.push(package.clone());
-        }
-    }
+    let untracked: Vec<String> = packages
+        .iter()
+        .filter(|package| !config.packages.contains(package))
+        .cloned()
+        .collect();
+    provenance::track(&mut config, &packages, Source::Add);
 
     if let Err(e) = save_systemfile(&config) {
         eprintln!("{} Failed to save systemfile: {}", RED_CROSS, e);
         return;
     }
+    journal::complete();
 
-    match OpenOptions::new()
-        .write(true)
-        .create(true)
-        .truncate(true)
-        .open(&manual_install_path)
-    {
-        Ok(file) => {
-            let writer = BufWriter::new(file);
-            if let Err(e) = serde_yaml_ng::to_writer(writer, &manual_packages) {
-                eprintln!("{} Failed to write manual packages: {}", RED_CROSS, e);
-                return;
-            }
-        }
-        Err(e) => {
-            eprintln!(
-                "{} Failed to create/open manual-install.yaml: {}",
-                RED_CROSS, e
-            );
-            return;
+    declare_manual_install(&config.folder, &untracked);
+}
+
+// Packages installed through `add` stay declared in the manual install file
+fn declare_manual_install(folder: &str, packages: &[String]) {
+    if packages.is_empty() {
+        return;
+    }
+    let manual_install_path = PathBuf::from(folder).join(provenance::MANUAL_INSTALL_FILE);
+    let mut manual_install = if manual_install_path.exists() {
+        PackageFile::read(&manual_install_path).unwrap_or_default()
+    } else {
+        PackageFile::default()
+    };
+
+    for package in packages {
+        if !manual_install.packages().iter().any(|entry| entry.name() == package) {
+            manual_install.packages_mut().push(PackageEntry::Name(package.clone()));
         }
     }
+
+    if let Err(e) = manual_install.write(&manual_install_path) {
+        eprintln!("{} Failed to write manual packages: {}", RED_CROSS, e);
+    }
 }
 
 fn update_package_files(packages_folder: &str, packages: &[String]) -> Result<(), String> {
@@ -606,31 +830,26 @@ fn process_yaml_file(path: &Path, packages: &[String]) -> Result<(), String> {
         .and_then(|n| n.to_str())
         .ok_or_else(|| format!("Invalid filename"))?;
 
-    let file = File::open(path)
-        .map_err(|e| format!("{} Failed to open {}: {}", YELLOW_WARNING, filename, e))?;
-    
-    let mut file_packages: Vec<String> = serde_yaml_ng::from_reader(BufReader::new(file))
-        .map_err(|_| format!("{} Failed to parse {}", YELLOW_WARNING, filename))?;
+    let mut package_file = PackageFile::read(path)
+        .map_err(|e| format!("{} Failed to parse {}: {}", YELLOW_WARNING, filename, e))?;
 
-    let original_len = file_packages.len();
-    file_packages.retain(|pkg| !packages.contains(pkg));
+    let original_len = package_file.packages().len();
+    package_file
+        .packages_mut()
+        .retain(|entry| !packages.iter().any(|package| package == entry.name()));
 
-    if file_packages.len() == original_len {
+    if package_file.packages().len() == original_len {
         return Ok(()); // No changes needed
     }
 
-    if file_packages.is_empty() {
+    // Other sections such as repositories keep the file alive
+    if package_file.is_empty() {
         fs::remove_file(path)
             .map_err(|e| format!("{} Failed to delete {}: {}", RED_CROSS, filename, e))?;
         println!("{} Deleted empty file: {}", GREEN_CHECK, filename);
     } else {
-        let file = OpenOptions::new()
-            .write(true)
-            .truncate(true)
-            .open(path)
-            .map_err(|e| format!("{} Failed to open {} for writing: {}", RED_CROSS, filename, e))?;
-        
-        serde_yaml_ng::to_writer(BufWriter::new(file), &file_packages)
+        package_file
+            .write(path)
             .map_err(|e| format!("{} Failed to write {}: {}", RED_CROSS, filename, e))?;
         
         println!("{} Updated {}", GREEN_CHECK, filename);
@@ -645,50 +864,146 @@ fn uninstall_package(packages: &[String]) {
         return;
     }
 
-    let remove_command = format!("pacman -Rns {}", packages.join(" "));
+    let remove_command = format!(
+        "pacman{} -Rns{} {}",
+        paths::root_args(),
+        noconfirm_flag(),
+        packages.join(" ")
+    );
 
+    journal::begin(Operation::Remove, packages, None);
+    let started = timestamp::now();
     run_command(&remove_command,true);
 
+    let (packages, failed): (Vec<String>, Vec<String>) = packages
+        .iter()
+        .cloned()
+        .partition(|package| !database::is_installed(package));
+    if !failed.is_empty() {
+        eprintln!("{} Packages still installed :\n{:?}", YELLOW_WARNING, failed);
+    }
+    history::record("remove", "remove command", started);
+
     let mut config = load_config();
-    config.packages.retain(|pkg| !packages.contains(pkg));
+    provenance::untrack(&mut config, &packages);
 
     if let Err(e) = save_systemfile(&config) {
         eprintln!("{} Failed to save systemfile: {}", RED_CROSS, e);
         return;
     }
+    journal::complete();
 
-    update_package_files(&config.folder, packages).expect("Failed to update reomved packages");
+    update_package_files(&config.folder, &packages).expect("Failed to update reomved packages");
 
     println!("{} Package removal complete", GREEN_CHECK);
 }
 
-fn manage_package() {
+fn ensure_paru() {
     if !check_package_installed("paru") {
         println!("{} Paru not installed, installing now", YELLOW_WARNING);
         run_command("pacman -S --noconfirm paru", true);
     }
-    remove_packages();
-    install_packages();
+}
+
+// Without --needed, the same version from another repository is replaced too
+fn repin_packages(tobe_repinned: Vec<String>) {
+    if tobe_repinned.is_empty() {
+        return;
+    }
+    let targets = install_targets(&tobe_repinned);
+    println!("Packages to reinstall from their pinned repository :\n{:?}", &targets);
+    if !ask_confirmation("Do you want to proceed reinstalling above packages [Y/n] : ") {
+        return;
+    }
+
+    let install_command = if is_offline() {
+        format!("pacman{} -S --noconfirm -- {}", paths::root_args(), targets.join(" "))
+    } else {
+        format!("paru{} -S --noconfirm -- {}", paths::root_args(), targets.join(" "))
+    };
+    let started = timestamp::now();
+    if run_command(&install_command, is_offline()) {
+        history::record("install", "repository pin", started);
+        println!("{} Packages reinstalled from their pinned repository", GREEN_CHECK);
+    }
+}
+
+fn apply_plan(plan: Plan) {
+    remove_packages(plan.remove);
+    install_packages(plan.install);
+    repin_packages(plan.repin);
+}
+
+fn manage_package() {
+    if !is_offline() {
+        ensure_paru();
+    }
+    let plan = match plan_changes() {
+        Ok(plan) => plan,
+        Err(e) => {
+            eprintln!("{} Error reading packages :: {}", RED_CROSS, e);
+            return;
+        }
+    };
+    if is_offline() {
+        offline::ensure_available(&plan.install, &plan.repin);
+    }
+
+    let snapshot = if plan.is_empty() {
+        None
+    } else {
+        snapshot::pre(&format!("novarch install: {}", plan.summary()))
+    };
+    apply_plan(plan);
+    snapshot::post(snapshot);
 }
 
 
-fn initialize() {
+fn initialize(folder: Option<&str>) {
     if !check_package_installed("rustup") {
         run_command("pacman -S --noconfirm rustup", true);
         run_command("rustup default stable", false);
     }
     
-    setup_check();
-    chaotic_aur_setup();
-    update_system();
+    setup_check(folder);
+    let declared = read_folder_declarations();
+    if let Some(declared) = &declared {
+        let initial = repositories::initial(declared, &load_config());
+        converge_folder(declared, &initial);
+        if !initial.is_empty() {
+            let mut config = load_config();
+            config.builtin_repositories_offered = true;
+            if let Err(e) = save_systemfile(&config) {
+                eprintln!("{} Failed to save systemfile: {}", RED_CROSS, e);
+            }
+        }
+    }
+    update_system(declared.as_ref());
     manage_package();
 }
 
 fn update() {
-    update_system();
-    manage_package();
+    let plan = plan_changes()
+        .map_err(|e| eprintln!("{} Error reading packages :: {}", RED_CROSS, e))
+        .ok();
+    if is_offline()
+        && let Some(plan) = &plan
+    {
+        offline::ensure_available(&plan.install, &plan.repin);
+    }
+    let summary = plan.as_ref().map_or("no package changes".to_string(), Plan::summary);
+    let snapshot = snapshot::pre(&format!("novarch update: system upgrade, {}", summary));
 
-    let alpm = Alpm::new("/", "/var/lib/pacman").expect("Failed to read database");
+    let declared = read_folder_declarations();
+    if let Some(declared) = &declared {
+        converge_folder(declared, &[]);
+    }
+    update_system(declared.as_ref());
+    if let Some(plan) = plan {
+        apply_plan(plan);
+    }
+
+    let alpm = database::open();
     let db = alpm.localdb();
     
     let orphans: Vec<String> = db
@@ -704,16 +1019,46 @@ fn update() {
     
     if !orphans.is_empty() {
         let orphan_list = orphans.join(" ");
-        run_command(&format!("pacman -Rns {}", orphan_list), true);
+        let started = timestamp::now();
+        if run_command(
+            &format!("pacman{} -Rns{} {}", paths::root_args(), noconfirm_flag(), orphan_list),
+            true,
+        ) {
+            history::record("remove", "orphan sweep", started);
+        }
     }
+    snapshot::post(snapshot);
 }
 
-fn info() {
+fn info(package: Option<&str>) {
     let config = load_config();
+    let declarations = declarations::read_declarations(&config.folder).unwrap_or_default();
+
+    if let Some(package) = package {
+        if config.packages.iter().any(|pkg| pkg == package) {
+            println!("\n{} : {}", package, provenance::describe(&config, &declarations, package));
+        } else {
+            println!("\n{} is not managed", package);
+        }
+        return;
+    }
+
     let no_of_packages = config.packages.len();
-    let folder = config.folder;
-    println!("\nPackages folder : {}", folder);
+    let added = config
+        .provenance
+        .values()
+        .filter(|provenance| provenance.source == Source::Add)
+        .count();
+    let from_folder = config
+        .provenance
+        .values()
+        .filter(|provenance| provenance.source == Source::Folder)
+        .count();
+    println!("\nPackages folder : {}", config.folder);
     println!("No of packages installed : {}", no_of_packages);
+    println!("  from folder declarations : {}", from_folder);
+    println!("  added manually : {}", added);
+    println!("  origin unknown : {}", no_of_packages.saturating_sub(added + from_folder));
 }
 
 #[derive(Parser)]
@@ -724,18 +1069,56 @@ fn info() {
 struct Cli {
     #[command(subcommand)]
     command: Commands,
+    /// Never prompt, answer every question with its default
+    #[arg(short = 'y', long = "yes", visible_alias = "non-interactive", global = true)]
+    yes: bool,
+    /// How to gain root for privileged steps, also read from NOVARCH_ESCALATION
+    #[arg(long, global = true, value_enum)]
+    escalation: Option<Escalation>,
+    /// Install into a mounted target, like pacstrap, state is kept inside it
+    #[arg(long, global = true)]
+    root: Option<String>,
+    /// Directory holding system.yaml, generations and the journal, also read from NOVARCH_STATE_DIR
+    #[arg(long, global = true)]
+    state_dir: Option<String>,
+    /// Path of system.yaml, also read from NOVARCH_CONFIG
+    #[arg(long, global = true)]
+    config: Option<String>,
+    /// Seconds to wait for another run or pacman to release its lock
+    #[arg(long, global = true, default_value_t = 60)]
+    lock_timeout: u64,
 }
 
 #[derive(Subcommand)]
 enum Commands {
     #[command(name = "init")]
-    Init,
+    Init {
+        /// Packages folder to use instead of prompting for one
+        #[arg(long)]
+        folder: Option<String>,
+    },
     #[command(name = "install")]
-    Install,
+    Install {
+        /// Install the exact versions recorded in novarch.lock
+        #[arg(long)]
+        locked: bool,
+        /// Only use the package cache and local repositories
+        #[arg(long)]
+        offline: bool,
+    },
+    #[command(name = "lock")]
+    Lock,
     #[command(name = "update")]
-    Update,
+    Update {
+        /// Skip mirrors and database sync, only use the package cache and local repositories
+        #[arg(long)]
+        offline: bool,
+    },
     #[command(name = "info")]
-    Info,
+    Info {
+        /// Explain where a managed package came from
+        package: Option<String>,
+    },
     #[command(name = "add")]
     Add {
         packages: Vec<String>,
@@ -744,6 +1127,45 @@ enum Commands {
     Remove {
         packages: Vec<String>,
     },
+    #[command(name = "resume")]
+    Resume,
+    #[command(name = "repair")]
+    Repair,
+    #[command(name = "rollback")]
+    Rollback {
+        /// Generation to return to, defaults to the one before the latest
+        number: Option<u32>,
+    },
+    #[command(name = "generations")]
+    Generations {
+        #[command(subcommand)]
+        action: GenerationsAction,
+    },
+    #[command(name = "history")]
+    History {
+        /// Only show changes from this day on, as YYYY-MM-DD
+        #[arg(long)]
+        since: Option<String>,
+        /// Only show changes up to and including this day, as YYYY-MM-DD
+        #[arg(long)]
+        until: Option<String>,
+        /// Only show changes to this package
+        #[arg(long)]
+        package: Option<String>,
+        /// Only show changes made by this tool or outside of it
+        #[arg(long, value_enum)]
+        source: Option<history::HistorySource>,
+    },
+}
+
+#[derive(Subcommand)]
+enum GenerationsAction {
+    #[command(name = "list")]
+    List,
+    #[command(name = "show")]
+    Show {
+        number: u32,
+    },
 }
 
 fn main() {
@@ -756,30 +1178,97 @@ fn main() {
     }
 
     let cli = Cli::parse();
+    let _ = NON_INTERACTIVE.set(cli.yes || !io::stdin().is_terminal());
+    let _ = OFFLINE.set(matches!(
+        cli.command,
+        Commands::Install { offline: true, .. } | Commands::Update { offline: true }
+    ));
+    paths::configure(cli.root.clone(), cli.state_dir.clone(), cli.config.clone());
+    privilege::set_backend(cli.escalation);
+    lock::set_timeout(cli.lock_timeout);
+
+    // Info only reads state, everything else may touch system.yaml or pacman
+    let _run_lock = match cli.command {
+        Commands::Info { .. } | Commands::Generations { .. } | Commands::History { .. } => None,
+        _ => Some(lock::acquire()),
+    };
+
+    if paths::is_alternate_root() {
+        for directory in [paths::dbpath(), paths::cachedir()] {
+            if !create_directory(&directory) {
+                eprintln!("{} Failed to create {}", RED_CROSS, directory);
+                exit(1);
+            }
+        }
+    }
+
+    // Resuming replays a transaction, so it needs the run lock the read-only
+    // commands skip
+    if _run_lock.is_some() && !matches!(cli.command, Commands::Resume) && journal::has_pending() {
+        println!("{} Previous run did not finish", YELLOW_WARNING);
+        journal::resume();
+    }
+
+    let command_line = env::args().skip(1).collect::<Vec<_>>().join(" ");
     match &cli.command {
-        Commands::Init => {
+        Commands::Init { folder } => {
             println!("{} Initializing...", BLUE_GEAR);
-            initialize();
+            initialize(folder.as_deref());
         }
-        Commands::Install => {
+        Commands::Install { locked, .. } => {
             println!("{} Installing...", BLUE_GEAR);
-            manage_package();
+            if *locked {
+                lockfile::install_locked();
+            } else {
+                manage_package();
+            }
+            generations::record(&command_line);
+        }
+        Commands::Lock => {
+            println!("{} Locking...", BLUE_GEAR);
+            lockfile::lock();
         }
-        Commands::Update => {
+        Commands::Update { .. } => {
             println!("{} Updating...", BLUE_GEAR);
             update();
+            generations::record(&command_line);
         }
-        Commands::Info => {
+        Commands::Info { package } => {
             println!("{} Showing info...", BLUE_GEAR);
-            info();
+            info(package.as_deref());
         }
         Commands::Add { packages } => {
             println!("{} Adding packages...", BLUE_GEAR);
             add_package(packages);
+            generations::record(&command_line);
         }
         Commands::Remove { packages } => {
             println!("{} Removing packages...", BLUE_GEAR);
             uninstall_package(packages);
+            generations::record(&command_line);
+        }
+        Commands::Repair => {
+            println!("{} Repairing...", BLUE_GEAR);
+            repair::repair();
+        }
+        Commands::Resume => {
+            println!("{} Resuming...", BLUE_GEAR);
+            journal::resume();
+        }
+        Commands::Rollback { number } => {
+            println!("{} Rolling back...", BLUE_GEAR);
+            rollback::rollback(*number);
+            generations::record(&command_line);
         }
+        Commands::Generations { action } => match action {
+            GenerationsAction::List => generations::list(),
+            GenerationsAction::Show { number } => generations::show(*number),
+        },
+        Commands::History {
+            since,
+            until,
+            package,
+            source,
+        } => history::history(since.as_deref(), until.as_deref(), package.as_deref(), *source),
     }
 }
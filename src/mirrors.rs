@@ -0,0 +1,121 @@
+// How the mirrorlist is kept up to date, declared in the packages folder
+use crate::declarations::FolderDeclarations;
+use crate::snapshot::shell_quote;
+use crate::{
+    BLUE_GEAR, GREEN_CHECK, RED_CROSS, YELLOW_WARNING, check_package_installed, paths,
+    try_command, write_privileged_file,
+};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::Path;
+
+// Included by the host pacman.conf, which also drives installs into a target root
+const MIRRORLIST: &str = "/etc/pacman.d/mirrorlist";
+const DEFAULT_REFLECTOR_ARGUMENTS: &str = "--latest 10 --protocol https --sort rate";
+
+fn default_reflector_arguments() -> String {
+    DEFAULT_REFLECTOR_ARGUMENTS.to_string()
+}
+
+fn default_latest() -> u32 {
+    10
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(tag = "strategy", rename_all = "kebab-case")]
+pub enum MirrorStrategy {
+    // Run reflector with these arguments, --save is added
+    Reflector {
+        #[serde(default = "default_reflector_arguments")]
+        arguments: String,
+    },
+    // Fastest https mirrors of these countries, through reflector
+    Country {
+        countries: Vec<String>,
+        #[serde(default = "default_latest")]
+        latest: u32,
+    },
+    // Mirrorlist kept in the packages folder, copied as is
+    Static { file: String },
+    // Leave the mirrorlist alone, e.g. in air-gapped labs
+    Unmanaged,
+}
+
+impl Default for MirrorStrategy {
+    fn default() -> Self {
+        MirrorStrategy::Reflector {
+            arguments: default_reflector_arguments(),
+        }
+    }
+}
+
+fn has_servers(mirrorlist: &str) -> bool {
+    mirrorlist
+        .lines()
+        .any(|line| line.trim_start().starts_with("Server"))
+}
+
+fn replace_mirrorlist(content: &str) {
+    if !has_servers(content) {
+        eprintln!("{} New mirrorlist has no servers, keeping the current one", YELLOW_WARNING);
+        return;
+    }
+    if fs::read_to_string(MIRRORLIST).is_ok_and(|current| current == content) {
+        println!("{} Mirrorlist is up to date", GREEN_CHECK);
+        return;
+    }
+    match write_privileged_file(MIRRORLIST, content) {
+        Ok(_) => println!("{} Mirrorlist updated", GREEN_CHECK),
+        Err(e) => eprintln!("{} Failed to write {}: {}", RED_CROSS, MIRRORLIST, e),
+    }
+}
+
+// A failing reflector only leaves the current mirrorlist in place
+fn run_reflector(arguments: &str) {
+    if !check_package_installed("reflector") {
+        println!("{} Reflector not installed, installing now", YELLOW_WARNING);
+        if !try_command("pacman -S --noconfirm reflector", true) {
+            eprintln!("{} Keeping the current mirrorlist", YELLOW_WARNING);
+            return;
+        }
+    }
+
+    // Reflector runs as root, only root can write the state directory, unlike
+    // a predictable path in /tmp
+    let output = paths::state_file("mirrorlist.reflector");
+    let command = format!("reflector {} --save {}", arguments, shell_quote(&output));
+    if !try_command(&command, true) {
+        eprintln!("{} Reflector failed, keeping the current mirrorlist", YELLOW_WARNING);
+        return;
+    }
+    match fs::read_to_string(&output) {
+        Ok(content) => replace_mirrorlist(&content),
+        Err(e) => eprintln!("{} Failed to read reflector output: {}", YELLOW_WARNING, e),
+    }
+    try_command(&format!("rm -f -- {}", shell_quote(&output)), true);
+}
+
+pub fn refresh(declared: &FolderDeclarations) {
+    match declared.mirrors.clone().unwrap_or_default() {
+        MirrorStrategy::Reflector { arguments } => {
+            println!("{} Refreshing mirrorlist with reflector", BLUE_GEAR);
+            run_reflector(&arguments);
+        }
+        MirrorStrategy::Country { countries, latest } => {
+            println!("{} Refreshing mirrorlist for {}", BLUE_GEAR, countries.join(", "));
+            run_reflector(&format!(
+                "--country {} --latest {} --protocol https --sort rate",
+                shell_quote(&countries.join(",")),
+                latest
+            ));
+        }
+        MirrorStrategy::Static { file } => {
+            let path = Path::new(&declared.folder).join(&file);
+            match fs::read_to_string(&path) {
+                Ok(content) => replace_mirrorlist(&content),
+                Err(e) => eprintln!("{} Failed to read {}: {}", RED_CROSS, path.display(), e),
+            }
+        }
+        MirrorStrategy::Unmanaged => {}
+    }
+}
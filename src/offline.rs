@@ -0,0 +1,79 @@
+// Installs without network: everything has to come from the package cache or
+// a file:// repository, checked before anything starts
+use crate::{RED_CROSS, cache, database, install_targets};
+use std::process::exit;
+
+// Packages, with the dependencies they pull in, that cannot be installed
+// from local files. Targets may be `repo/name` for pinned packages.
+pub fn unavailable(packages: &[String]) -> Vec<String> {
+    let alpm = database::open_with_syncdbs();
+    let localdb = alpm.localdb();
+
+    let mut seen: Vec<String> = Vec::new();
+    let mut missing = Vec::new();
+    let mut queue: Vec<String> = packages.to_vec();
+    while let Some(target) = queue.pop() {
+        if seen.contains(&target) {
+            continue;
+        }
+        seen.push(target.clone());
+
+        // Installed already, but from another repository than the pinned one
+        let pinned = target.split_once('/').and_then(|(repo, name)| {
+            alpm.syncdbs()
+                .iter()
+                .find(|db| db.name() == repo)?
+                .pkg(name)
+                .ok()
+        });
+        if pinned.is_none() && localdb.pkgs().find_satisfier(target.as_str()).is_some() {
+            continue;
+        }
+        let Some(pkg) = pinned.or_else(|| alpm.syncdbs().find_satisfier(target.as_str())) else {
+            let members: Vec<String> = alpm
+                .syncdbs()
+                .iter()
+                .filter_map(|db| db.group(target.as_str()).ok())
+                .flat_map(|group| {
+                    group
+                        .packages()
+                        .iter()
+                        .map(|pkg| pkg.name().to_string())
+                        .collect::<Vec<_>>()
+                })
+                .collect();
+            if members.is_empty() {
+                missing.push(format!("{} (in no sync database)", target));
+            }
+            queue.extend(members);
+            continue;
+        };
+
+        let arch = pkg.arch().unwrap_or("any");
+        if cache::find_local(pkg.name(), pkg.version().as_str(), arch).is_none() {
+            missing.push(format!("{} {}", pkg.name(), pkg.version()));
+        }
+        queue.extend(pkg.depends().iter().map(|dep| dep.to_string()));
+    }
+    missing.sort();
+    missing
+}
+
+// Pinned packages are checked against their pinned repository, as they are
+// installed as repo/name
+pub fn ensure_available(install: &[String], repin: &[String]) {
+    let mut packages = install_targets(install);
+    packages.extend(install_targets(repin));
+    let missing = unavailable(&packages);
+    if missing.is_empty() {
+        return;
+    }
+    eprintln!(
+        "{} Not available offline, add them to the package cache or a local repository :",
+        RED_CROSS
+    );
+    for package in &missing {
+        eprintln!("  {}", package);
+    }
+    exit(1);
+}
@@ -0,0 +1,644 @@
+// pacman.conf as sections of raw lines, so that edits keep comments and ordering
+use crate::database::PACMAN_CONF;
+use crate::declarations::FolderDeclarations;
+use crate::repositories::{self, Repository};
+use crate::{
+    BLUE_GEAR, Config, GREEN_CHECK, RED_CROSS, ask_confirmation, is_offline, load_config, paths,
+    run_command, save_systemfile, write_privileged_file,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::io;
+
+const OPTIONS_SECTION: &str = "options";
+
+// Declared value of a global option: `true` enables a bare flag such as Color,
+// `false` or an empty list comments the option out
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(untagged)]
+pub enum OptionValue {
+    Flag(bool),
+    Number(u64),
+    List(Vec<String>),
+    Text(String),
+}
+
+impl OptionValue {
+    // None when disabled, Some(None) for a bare flag
+    fn rendered(&self) -> Option<Option<String>> {
+        match self {
+            OptionValue::Flag(true) => Some(None),
+            OptionValue::Flag(false) => None,
+            OptionValue::Number(number) => Some(Some(number.to_string())),
+            OptionValue::List(values) if values.is_empty() => None,
+            OptionValue::List(values) => Some(Some(values.join(" "))),
+            OptionValue::Text(text) => Some(Some(text.clone())),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Section {
+    // None for the lines before the first header
+    pub name: Option<String>,
+    header: Option<String>,
+    lines: Vec<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct PacmanConf {
+    sections: Vec<Section>,
+}
+
+fn header_name(line: &str) -> Option<&str> {
+    line.trim().strip_prefix('[')?.strip_suffix(']').map(str::trim)
+}
+
+// pacman.conf keys are single words such as Color or SigLevel, which tells
+// them apart from the prose of commented out lines
+fn is_key(key: &str) -> bool {
+    !key.is_empty() && key.chars().all(|c| c.is_ascii_alphabetic())
+}
+
+// `Key = value` or a bare `Key`, None for comments, blank lines and prose
+fn parse_option(line: &str) -> Option<(String, Option<String>)> {
+    let line = line.trim();
+    if line.is_empty() || line.starts_with('#') {
+        return None;
+    }
+    let (key, value) = match line.split_once('=') {
+        Some((key, value)) => (key.trim(), Some(value.trim().to_string())),
+        None => (line, None),
+    };
+    is_key(key).then(|| (key.to_string(), value))
+}
+
+fn format_option(key: &str, value: Option<&str>) -> String {
+    match value {
+        Some(value) => format!("{} = {}", key, value),
+        None => key.to_string(),
+    }
+}
+
+// `#Include = ...` style lines of a commented out section
+fn uncomment(line: &str) -> Option<String> {
+    let rest = line.trim().strip_prefix('#')?.trim_start();
+    parse_option(rest).map(|(key, value)| format_option(&key, value.as_deref()))
+}
+
+impl Section {
+    pub fn options(&self) -> Vec<(String, Option<String>)> {
+        self.lines.iter().filter_map(|line| parse_option(line)).collect()
+    }
+
+    // Options normalized to `Key = value`, in file order
+    pub fn settings(&self) -> Vec<String> {
+        self.options()
+            .iter()
+            .map(|(key, value)| format_option(key, value.as_deref()))
+            .collect()
+    }
+
+    fn is_option(line: &str, key: &str) -> bool {
+        parse_option(line).is_some_and(|(name, _)| name == key)
+    }
+
+    // Set one option in place: the enabled line is rewritten, a commented out
+    // one is enabled, otherwise it goes after the last option
+    fn set_option(&mut self, key: &str, value: Option<&str>) {
+        let line = format_option(key, value);
+        if let Some(index) = self.lines.iter().position(|line| Self::is_option(line, key)) {
+            self.lines[index] = line;
+            // Repeated lines such as IgnorePkg add up, only the rewritten one stays
+            self.lines = self
+                .lines
+                .drain(..)
+                .enumerate()
+                .filter(|(position, line)| *position <= index || !Self::is_option(line, key))
+                .map(|(_, line)| line)
+                .collect();
+        } else if let Some(index) = self
+            .lines
+            .iter()
+            .position(|line| uncomment(line).is_some_and(|option| Self::is_option(&option, key)))
+        {
+            self.lines[index] = line;
+        } else {
+            let index = self
+                .lines
+                .iter()
+                .rposition(|line| parse_option(line).is_some())
+                .or_else(|| self.lines.iter().rposition(|line| !line.trim().is_empty()))
+                .map_or(0, |index| index + 1);
+            self.lines.insert(index, line);
+        }
+    }
+
+    fn disable_option(&mut self, key: &str) {
+        for line in self.lines.iter_mut() {
+            if Self::is_option(line, key) {
+                *line = format!("#{}", line.trim());
+            }
+        }
+    }
+
+    // Replace every option line, the first new one taking the place of the
+    // first old one so that surrounding comments stay put
+    fn set_settings(&mut self, settings: &[String]) {
+        // Only comments and blank lines come before it, so it survives the retain
+        let position = self
+            .lines
+            .iter()
+            .position(|line| parse_option(line).is_some())
+            .unwrap_or_else(|| {
+                self.lines
+                    .iter()
+                    .rposition(|line| !line.trim().is_empty())
+                    .map_or(0, |index| index + 1)
+            });
+        self.lines.retain(|line| parse_option(line).is_none());
+        self.lines.splice(position..position, settings.iter().cloned());
+    }
+}
+
+impl PacmanConf {
+    pub fn parse(content: &str) -> Self {
+        let mut sections = vec![Section {
+            name: None,
+            header: None,
+            lines: Vec::new(),
+        }];
+        for line in content.lines() {
+            match header_name(line) {
+                Some(name) => sections.push(Section {
+                    name: Some(name.to_string()),
+                    header: Some(line.to_string()),
+                    lines: Vec::new(),
+                }),
+                None => sections
+                    .last_mut()
+                    .expect("there is always a section")
+                    .lines
+                    .push(line.to_string()),
+            }
+        }
+        PacmanConf { sections }
+    }
+
+    pub fn read(path: &str) -> io::Result<Self> {
+        fs::read_to_string(path).map(|content| Self::parse(&content))
+    }
+
+    pub fn section(&self, name: &str) -> Option<&Section> {
+        self.sections.iter().find(|section| section.name.as_deref() == Some(name))
+    }
+
+    fn section_mut(&mut self, name: &str) -> Option<&mut Section> {
+        self.sections
+            .iter_mut()
+            .find(|section| section.name.as_deref() == Some(name))
+    }
+
+    // Converge a global option of the [options] section
+    pub fn set_option(&mut self, key: &str, value: &OptionValue) {
+        if self.section(OPTIONS_SECTION).is_none() {
+            let position = self.sections.len().min(1);
+            self.sections.insert(
+                position,
+                Section {
+                    name: Some(OPTIONS_SECTION.to_string()),
+                    header: Some(format!("[{}]", OPTIONS_SECTION)),
+                    lines: vec![String::new()],
+                },
+            );
+        }
+        let Some(section) = self.section_mut(OPTIONS_SECTION) else {
+            return;
+        };
+        match value.rendered() {
+            Some(rendered) => section.set_option(key, rendered.as_deref()),
+            None => section.disable_option(key),
+        }
+    }
+
+    // Drop a repository's header and options, comments around it stay
+    pub fn remove_section(&mut self, name: &str) -> bool {
+        let Some(index) = self
+            .sections
+            .iter()
+            .position(|section| section.name.as_deref() == Some(name))
+        else {
+            return false;
+        };
+        let removed = self.sections.remove(index);
+        let kept: Vec<String> = removed
+            .lines
+            .into_iter()
+            .filter(|line| parse_option(line).is_none())
+            .skip_while(|line| line.trim().is_empty())
+            .collect();
+        self.sections[index - 1].lines.extend(kept);
+        true
+    }
+
+    // Enabled repositories in priority order
+    pub fn repositories(&self) -> Vec<String> {
+        self.sections
+            .iter()
+            .filter_map(|section| section.name.clone())
+            .filter(|name| name != "options")
+            .collect()
+    }
+
+    // Turn a shipped `#[name]` block and its commented options back on
+    pub fn uncomment_section(&mut self, name: &str) -> bool {
+        let content = self.to_string();
+        let mut lines: Vec<String> = content.lines().map(str::to_string).collect();
+        let Some(start) = lines.iter().position(|line| {
+            line.trim()
+                .strip_prefix('#')
+                .and_then(header_name)
+                .is_some_and(|header| header == name)
+        }) else {
+            return false;
+        };
+
+        lines[start] = format!("[{}]", name);
+        for line in lines.iter_mut().skip(start + 1) {
+            match uncomment(line) {
+                Some(option) => *line = option,
+                None => break,
+            }
+        }
+        *self = Self::parse(&lines.join("\n"));
+        true
+    }
+
+    // Give a section exactly these options: the existing one is rewritten, a
+    // commented out one is enabled in place, otherwise it is appended
+    pub fn set_section(&mut self, name: &str, settings: &[String]) {
+        if self.section(name).is_none() && !self.uncomment_section(name) {
+            if let Some(last) = self.sections.last_mut()
+                && last.lines.last().is_some_and(|line| !line.trim().is_empty())
+            {
+                last.lines.push(String::new());
+            }
+            self.sections.push(Section {
+                name: Some(name.to_string()),
+                header: Some(format!("[{}]", name)),
+                lines: Vec::new(),
+            });
+        }
+        if let Some(section) = self.section_mut(name) {
+            section.set_settings(settings);
+        }
+    }
+}
+
+impl fmt::Display for PacmanConf {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for section in &self.sections {
+            if let Some(header) = &section.header {
+                writeln!(f, "{}", header)?;
+            }
+            for line in &section.lines {
+                writeln!(f, "{}", line)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+// Changed lines between two versions, as - and + lines in file order
+pub fn diff(old: &str, new: &str) -> Vec<String> {
+    let old: Vec<&str> = old.lines().collect();
+    let new: Vec<&str> = new.lines().collect();
+
+    // Longest common subsequence table, from the end of both files
+    let mut common = vec![vec![0usize; new.len() + 1]; old.len() + 1];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            common[i][j] = if old[i] == new[j] {
+                common[i + 1][j + 1] + 1
+            } else {
+                common[i + 1][j].max(common[i][j + 1])
+            };
+        }
+    }
+
+    let mut changes = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    while i < old.len() || j < new.len() {
+        if i < old.len() && j < new.len() && old[i] == new[j] {
+            i += 1;
+            j += 1;
+        } else if i < old.len() && (j == new.len() || common[i + 1][j] >= common[i][j + 1]) {
+            changes.push(format!("- {}", old[i]));
+            i += 1;
+        } else {
+            changes.push(format!("+ {}", new[j]));
+            j += 1;
+        }
+    }
+    changes
+}
+
+// Bring pacman.conf in line with the options and repositories of the packages
+// folder, showing the diff before anything is written. Initial repositories
+// are added like declared ones but never become managed.
+pub fn converge(declared: &FolderDeclarations, initial: &[Repository]) {
+    let mut declared_repositories = repositories::declared(declared);
+    declared_repositories.extend(initial.iter().cloned());
+    let original = match fs::read_to_string(PACMAN_CONF) {
+        Ok(content) => content,
+        Err(e) => {
+            eprintln!("{} Failed to read {}: {}", RED_CROSS, PACMAN_CONF, e);
+            return;
+        }
+    };
+
+    let mut conf = PacmanConf::parse(&original);
+    for (key, value) in &declared.pacman_options {
+        conf.set_option(key, value);
+    }
+    let mut config = load_config();
+    let owned = repositories::owned(declared);
+    adopt_repositories(&mut config, &conf, &declared_repositories, &owned);
+    let changed = repositories::apply(&mut conf, &declared_repositories);
+
+    let undeclared: Vec<String> = config
+        .managed_repositories
+        .iter()
+        .filter(|name| !owned.contains(name))
+        .filter(|name| conf.section(name).is_some())
+        .cloned()
+        .collect();
+    // Looked up while pacman still knows these repositories
+    let orphaned = repositories::installed_packages(&undeclared);
+    for name in &undeclared {
+        conf.remove_section(name);
+    }
+
+    let updated = conf.to_string();
+    let changes = diff(&original, &updated);
+    if changes.is_empty() {
+        return;
+    }
+
+    println!("{} Changes to {} :", BLUE_GEAR, PACMAN_CONF);
+    for change in &changes {
+        println!("  {}", change);
+    }
+    if !ask_confirmation("Do you want to apply these changes [Y/n] : ") {
+        return;
+    }
+
+    let bootstrapped = repositories::prepare(&changed);
+    if let Err(e) = write_privileged_file(PACMAN_CONF, &updated) {
+        eprintln!("{} Failed to modify {}: {}", RED_CROSS, PACMAN_CONF, e);
+        return;
+    }
+    println!("{} Updated {}", GREEN_CHECK, PACMAN_CONF);
+    let removed_bootstrap =
+        record_managed_repositories(&mut config, &changed, &owned, &undeclared, bootstrapped);
+
+    if (!changed.is_empty() || !undeclared.is_empty()) && !is_offline() {
+        run_command(&format!("pacman{} -Syu --noconfirm", paths::root_args()), true);
+    }
+    repositories::report_removed(&orphaned, &removed_bootstrap);
+}
+
+// A section set up before, by hand, by init's built-in list or an earlier
+// version, is taken over by a declared repository once its settings match,
+// so that it is removed again when no longer declared
+fn adopt_repositories(
+    config: &mut Config,
+    conf: &PacmanConf,
+    repositories: &[Repository],
+    owned: &[String],
+) {
+    let adopted: Vec<String> = repositories::matching(conf, repositories)
+        .into_iter()
+        .filter(|name| owned.contains(name) && !config.managed_repositories.contains(name))
+        .collect();
+    if adopted.is_empty() {
+        return;
+    }
+    for name in &adopted {
+        println!("{} Repository {} matches its declaration, now managed", GREEN_CHECK, name);
+    }
+    config.managed_repositories.extend(adopted);
+    if let Err(e) = save_systemfile(config) {
+        eprintln!("{} Failed to save systemfile: {}", RED_CROSS, e);
+    }
+}
+
+// Besides adopted ones, only sections this tool added for a repository of the
+// folder become managed, so that it never removes one it was not given.
+// Returns the bootstrap packages of the repositories that were removed.
+fn record_managed_repositories(
+    config: &mut Config,
+    changed: &[repositories::Change],
+    owned: &[String],
+    removed: &[String],
+    bootstrapped: BTreeMap<String, Vec<String>>,
+) -> BTreeMap<String, Vec<String>> {
+    config.managed_repositories.retain(|name| !removed.contains(name));
+    for name in changed.iter().filter_map(repositories::Change::added) {
+        let name = name.to_string();
+        if owned.contains(&name) && !config.managed_repositories.contains(&name) {
+            config.managed_repositories.push(name);
+        }
+    }
+    for (repository, packages) in bootstrapped {
+        if config.managed_repositories.contains(&repository) {
+            config.bootstrap_packages.entry(repository).or_default().extend(packages);
+        }
+    }
+    let removed_bootstrap = removed
+        .iter()
+        .filter_map(|name| Some((name.clone(), config.bootstrap_packages.remove(name)?)))
+        .collect();
+
+    if let Err(e) = save_systemfile(config) {
+        eprintln!("{} Failed to save systemfile: {}", RED_CROSS, e);
+    }
+    removed_bootstrap
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Abridged from the pacman.conf shipped by pacman
+    const STOCK: &str = "\
+#
+# /etc/pacman.conf
+#
+# See the pacman.conf(5) manpage for option and repository directives
+
+[options]
+# The following paths are commented out with their default values listed.
+#RootDir     = /
+HoldPkg     = pacman glibc
+Architecture = auto
+
+# Misc options
+#UseSyslog
+#Color
+#NoProgressBar
+CheckSpace
+#ParallelDownloads = 5
+
+SigLevel    = Required DatabaseOptional
+
+[core]
+Include = /etc/pacman.d/mirrorlist
+
+[extra]
+Include = /etc/pacman.d/mirrorlist
+
+#[multilib]
+#Include = /etc/pacman.d/mirrorlist
+
+# An example of a custom package repository.  See the pacman manpage for
+# tips on creating your own repositories.
+#[custom]
+#SigLevel = Optional TrustAll
+#Server = file:///home/custompkgs
+";
+
+    #[test]
+    fn parse_and_display_round_trip() {
+        assert_eq!(PacmanConf::parse(STOCK).to_string(), STOCK);
+    }
+
+    #[test]
+    fn options_are_single_word_keys() {
+        assert_eq!(
+            parse_option("HoldPkg     = pacman glibc"),
+            Some(("HoldPkg".to_string(), Some("pacman glibc".to_string())))
+        );
+        assert_eq!(parse_option("CheckSpace"), Some(("CheckSpace".to_string(), None)));
+        assert_eq!(parse_option("#Color"), None);
+        assert_eq!(parse_option("   "), None);
+        assert_eq!(parse_option("An example of a custom package repository."), None);
+        assert_eq!(parse_option("See the manpage = here"), None);
+        assert_eq!(uncomment("#Color"), Some("Color".to_string()));
+        assert_eq!(uncomment("# tips on creating your own repositories."), None);
+    }
+
+    #[test]
+    fn repositories_in_priority_order() {
+        let conf = PacmanConf::parse(STOCK);
+        assert_eq!(conf.repositories(), vec!["core", "extra"]);
+        assert_eq!(
+            conf.section("core").unwrap().settings(),
+            vec!["Include = /etc/pacman.d/mirrorlist"]
+        );
+    }
+
+    #[test]
+    fn uncomment_section_enables_only_that_block() {
+        let mut conf = PacmanConf::parse(STOCK);
+        assert!(conf.uncomment_section("multilib"));
+        assert!(!conf.uncomment_section("missing"));
+
+        let updated = conf.to_string();
+        assert!(updated.contains("[multilib]\nInclude = /etc/pacman.d/mirrorlist\n"));
+        assert!(updated.contains("# An example of a custom package repository."));
+        assert!(updated.contains("#[custom]\n#SigLevel = Optional TrustAll\n"));
+        assert_eq!(conf.repositories(), vec!["core", "extra", "multilib"]);
+    }
+
+    #[test]
+    fn uncomment_section_stops_at_prose() {
+        let mut conf = PacmanConf::parse(
+            "[options]\n#[custom]\n# An example of a custom package repository\n#Server = file:///x\n",
+        );
+        assert!(conf.uncomment_section("custom"));
+        assert_eq!(
+            conf.to_string(),
+            "[options]\n[custom]\n# An example of a custom package repository\n#Server = file:///x\n"
+        );
+    }
+
+    #[test]
+    fn set_option_rewrites_enables_or_appends() {
+        let mut conf = PacmanConf::parse(STOCK);
+        conf.set_option("HoldPkg", &OptionValue::List(vec!["pacman".to_string()]));
+        conf.set_option("Color", &OptionValue::Flag(true));
+        conf.set_option("ParallelDownloads", &OptionValue::Number(10));
+        conf.set_option("CheckSpace", &OptionValue::Flag(false));
+        conf.set_option("ILoveCandy", &OptionValue::Flag(true));
+
+        let updated = conf.to_string();
+        assert!(updated.contains("\nHoldPkg = pacman\n"));
+        assert!(updated.contains("#UseSyslog\nColor\n#NoProgressBar\n#CheckSpace\nParallelDownloads = 10\n"));
+        assert!(updated.contains("SigLevel    = Required DatabaseOptional\nILoveCandy\n\n[core]"));
+        assert!(updated.contains("# The following paths are commented out"));
+    }
+
+    #[test]
+    fn set_option_keeps_one_of_repeated_lines() {
+        let mut conf = PacmanConf::parse("[options]\nIgnorePkg = a\n# keep\nIgnorePkg = b\n");
+        conf.set_option("IgnorePkg", &OptionValue::Text("c".to_string()));
+        assert_eq!(conf.to_string(), "[options]\nIgnorePkg = c\n# keep\n");
+    }
+
+    #[test]
+    fn set_section_keeps_surrounding_comments() {
+        let mut conf = PacmanConf::parse(STOCK);
+        conf.set_section("extra", &["Server = https://example.org/$repo".to_string()]);
+        conf.set_section("chaotic-aur", &["Include = /etc/pacman.d/chaotic-mirrorlist".to_string()]);
+
+        let updated = conf.to_string();
+        assert!(updated.contains("[extra]\nServer = https://example.org/$repo\n\n#[multilib]"));
+        assert!(updated.ends_with(
+            "#Server = file:///home/custompkgs\n\n[chaotic-aur]\nInclude = /etc/pacman.d/chaotic-mirrorlist\n"
+        ));
+    }
+
+    #[test]
+    fn existing_sections_match_their_declaration() {
+        let conf = PacmanConf::parse(
+            "[core]\nInclude = /etc/pacman.d/mirrorlist\n\n[chaotic-aur]\nInclude = /etc/pacman.d/chaotic-mirrorlist\n",
+        );
+        let repository = |name: &str, include: &str| Repository {
+            name: name.to_string(),
+            server: Vec::new(),
+            include: Some(include.to_string()),
+            sig_level: None,
+            keys: Vec::new(),
+            keyserver: None,
+            bootstrap: Vec::new(),
+        };
+        let declared = [
+            repository("chaotic-aur", "/etc/pacman.d/chaotic-mirrorlist"),
+            repository("core", "/etc/pacman.d/other-mirrorlist"),
+            repository("multilib", "/etc/pacman.d/mirrorlist"),
+        ];
+        assert_eq!(repositories::matching(&conf, &declared), vec!["chaotic-aur"]);
+    }
+
+    #[test]
+    fn remove_section_keeps_comments() {
+        let mut conf = PacmanConf::parse("[core]\nInclude = a\n\n# note\n[extra]\nInclude = b\n");
+        assert!(conf.remove_section("extra"));
+        assert!(!conf.remove_section("extra"));
+        assert_eq!(conf.to_string(), "[core]\nInclude = a\n\n# note\n");
+    }
+
+    #[test]
+    fn diff_lists_removed_before_added_lines() {
+        assert!(diff(STOCK, STOCK).is_empty());
+        assert_eq!(
+            diff("a\nb\nc\n", "a\nB\nc\nd\n"),
+            vec!["- b", "+ B", "+ d"]
+        );
+        assert_eq!(diff("", "x\n"), vec!["+ x"]);
+        assert_eq!(diff("x\n", ""), vec!["- x"]);
+    }
+}
@@ -0,0 +1,85 @@
+// Locations of the tool's own state, overridable per run
+use std::env;
+use std::sync::OnceLock;
+
+const DEFAULT_STATE_DIRECTORY: &str = "/var/lib/novarch";
+pub const PACMAN_DBPATH: &str = "/var/lib/pacman";
+const PACMAN_CACHEDIR: &str = "/var/cache/pacman/pkg";
+
+static ROOT: OnceLock<String> = OnceLock::new();
+static STATE_DIRECTORY: OnceLock<String> = OnceLock::new();
+static SYSTEM_FILE: OnceLock<String> = OnceLock::new();
+
+// Flags win over NOVARCH_STATE_DIR and NOVARCH_CONFIG. The config file lives
+// inside the state directory unless given explicitly, and the state directory
+// lives inside the target root.
+pub fn configure(root: Option<String>, state_directory: Option<String>, system_file: Option<String>) {
+    let root = root
+        .map(|root| root.trim_end_matches('/').to_string())
+        .filter(|root| !root.is_empty())
+        .unwrap_or_else(|| "/".to_string());
+    let _ = ROOT.set(root);
+
+    let state_directory = state_directory
+        .or_else(|| env::var("NOVARCH_STATE_DIR").ok())
+        .unwrap_or_else(|| target(DEFAULT_STATE_DIRECTORY));
+    let system_file = system_file
+        .or_else(|| env::var("NOVARCH_CONFIG").ok())
+        .unwrap_or_else(|| format!("{}/system.yaml", state_directory.trim_end_matches('/')));
+
+    let _ = STATE_DIRECTORY.set(state_directory.trim_end_matches('/').to_string());
+    let _ = SYSTEM_FILE.set(system_file);
+}
+
+pub fn root() -> &'static str {
+    ROOT.get_or_init(|| "/".to_string())
+}
+
+pub fn is_alternate_root() -> bool {
+    root() != "/"
+}
+
+// An absolute path inside the target root
+pub fn target(path: &str) -> String {
+    if is_alternate_root() {
+        format!("{}{}", root(), path)
+    } else {
+        path.to_string()
+    }
+}
+
+pub fn dbpath() -> String {
+    target(PACMAN_DBPATH)
+}
+
+pub fn cachedir() -> String {
+    target(PACMAN_CACHEDIR)
+}
+
+// A fresh target root has no sync databases until they are downloaded once
+pub fn target_unsynced() -> bool {
+    is_alternate_root()
+        && std::fs::read_dir(format!("{}/sync", dbpath()))
+            .map_or(true, |mut entries| entries.next().is_none())
+}
+
+// Options pointing pacman and paru at the target root, empty for the host
+pub fn root_args() -> String {
+    if is_alternate_root() {
+        format!(" --root {} --dbpath {} --cachedir {}", root(), dbpath(), cachedir())
+    } else {
+        String::new()
+    }
+}
+
+pub fn state_directory() -> &'static str {
+    STATE_DIRECTORY.get_or_init(|| target(DEFAULT_STATE_DIRECTORY))
+}
+
+pub fn state_file(name: &str) -> String {
+    format!("{}/{}", state_directory(), name)
+}
+
+pub fn system_file() -> &'static str {
+    SYSTEM_FILE.get_or_init(|| state_file("system.yaml"))
+}
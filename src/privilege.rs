@@ -0,0 +1,121 @@
+// Privilege escalation backends and dropping back to the invoking user
+use crate::{ORIGINAL_USER, YELLOW_WARNING, is_non_interactive};
+use clap::ValueEnum;
+use std::env;
+use std::fs;
+use std::os::unix::fs::MetadataExt;
+use std::process::Command;
+use std::sync::OnceLock;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Escalation {
+    Auto,
+    Sudo,
+    Doas,
+    Run0,
+    Pkexec,
+    None,
+}
+
+static BACKEND: OnceLock<Escalation> = OnceLock::new();
+
+// /proc/self is owned by the effective user of the process
+pub fn is_root() -> bool {
+    fs::metadata("/proc/self")
+        .map(|metadata| metadata.uid() == 0)
+        .unwrap_or(false)
+}
+
+fn in_path(program: &str) -> bool {
+    env::var_os("PATH")
+        .map(|paths| env::split_paths(&paths).any(|dir| dir.join(program).is_file()))
+        .unwrap_or(false)
+}
+
+fn detect() -> Escalation {
+    if is_root() {
+        return Escalation::None;
+    }
+    [
+        ("sudo", Escalation::Sudo),
+        ("doas", Escalation::Doas),
+        ("run0", Escalation::Run0),
+        ("pkexec", Escalation::Pkexec),
+    ]
+    .into_iter()
+    .find(|(program, _)| in_path(program))
+    .map(|(_, backend)| backend)
+    .unwrap_or(Escalation::Sudo)
+}
+
+// The flag wins over NOVARCH_ESCALATION, both default to auto detection
+pub fn set_backend(choice: Option<Escalation>) {
+    let choice = choice
+        .or_else(|| {
+            env::var("NOVARCH_ESCALATION")
+                .ok()
+                .and_then(|value| Escalation::from_str(&value, true).ok())
+        })
+        .unwrap_or(Escalation::Auto);
+
+    let backend = match choice {
+        Escalation::Auto => detect(),
+        backend => backend,
+    };
+    let _ = BACKEND.set(backend);
+}
+
+fn backend() -> Escalation {
+    *BACKEND.get_or_init(detect)
+}
+
+// Escalation program and its arguments, empty when no escalation is needed
+fn escalation_args() -> Vec<&'static str> {
+    let non_interactive = is_non_interactive();
+    match backend() {
+        Escalation::Sudo if non_interactive => vec!["sudo", "-n"],
+        Escalation::Sudo => vec!["sudo"],
+        Escalation::Doas if non_interactive => vec!["doas", "-n"],
+        Escalation::Doas => vec!["doas"],
+        Escalation::Run0 if non_interactive => vec!["run0", "--no-ask-password"],
+        Escalation::Run0 => vec!["run0"],
+        Escalation::Pkexec => vec!["pkexec"],
+        Escalation::Auto | Escalation::None => Vec::new(),
+    }
+}
+
+// Prefix for shell command strings that must run as root
+pub fn prefix() -> String {
+    escalation_args().join(" ")
+}
+
+// Command running `program` as root
+pub fn command(program: &str) -> Command {
+    let args = escalation_args();
+    match args.split_first() {
+        Some((escalator, rest)) => {
+            let mut command = Command::new(escalator);
+            command.args(rest).arg(program);
+            command
+        }
+        None => Command::new(program),
+    }
+}
+
+// Prefix for shell command strings that must not run as root, like paru and
+// makepkg. Only needed when we are root ourselves and know who invoked us.
+pub fn user_prefix() -> String {
+    if !is_root() {
+        return String::new();
+    }
+    match ORIGINAL_USER.get() {
+        Some(user) if user != "root" => format!("runuser -u {} --", user),
+        _ => {
+            eprintln!(
+                "{} Running as root without an invoking user, unprivileged steps run as root",
+                YELLOW_WARNING
+            );
+            String::new()
+        }
+    }
+}
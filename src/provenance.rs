@@ -0,0 +1,86 @@
+// Where each managed package came from and when it was first installed
+use crate::declarations::{self, Declaration};
+use crate::{Config, timestamp};
+use serde::{Deserialize, Serialize};
+
+pub const MANUAL_INSTALL_FILE: &str = "manual-install.yaml";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Source {
+    // Installed through `add`
+    Add,
+    // Installed because the packages folder declares it
+    Folder,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Provenance {
+    pub source: Source,
+    pub file: Option<String>,
+    pub installed: u64,
+}
+
+// Start tracking packages, keeping the provenance of those already known
+pub fn track(config: &mut Config, packages: &[String], source: Source) {
+    let declarations = declarations::read_declarations(&config.folder).unwrap_or_default();
+    let now = timestamp::now();
+
+    for package in packages {
+        if !config.packages.contains(package) {
+            config.packages.push(package.clone());
+        }
+        if config.provenance.contains_key(package) {
+            continue;
+        }
+        let file = match source {
+            Source::Add => Some(MANUAL_INSTALL_FILE.to_string()),
+            Source::Folder => declarations::declaring_file(&declarations, package).map(str::to_string),
+        };
+        config.provenance.insert(
+            package.clone(),
+            Provenance {
+                source,
+                file,
+                installed: now,
+            },
+        );
+    }
+}
+
+pub fn untrack(config: &mut Config, packages: &[String]) {
+    config.packages.retain(|package| !packages.contains(package));
+    config.provenance.retain(|package, _| !packages.contains(package));
+}
+
+// Drop provenance of packages that are no longer tracked
+pub fn prune(config: &mut Config) {
+    let packages = config.packages.clone();
+    config.provenance.retain(|package, _| packages.contains(package));
+}
+
+pub fn describe(config: &Config, declarations: &[Declaration], package: &str) -> String {
+    let origin = match config.provenance.get(package) {
+        Some(provenance) => {
+            let how = match provenance.source {
+                Source::Add => "added manually",
+                Source::Folder => "declared in the folder",
+            };
+            match &provenance.file {
+                Some(file) => format!(
+                    "{} via {} on {}",
+                    how,
+                    file,
+                    timestamp::format(provenance.installed)
+                ),
+                None => format!("{} on {}", how, timestamp::format(provenance.installed)),
+            }
+        }
+        None => "origin unknown".to_string(),
+    };
+
+    match declarations::declaring_file(declarations, package) {
+        Some(file) => format!("{}, currently declared in {}", origin, file),
+        None => format!("{}, no longer declared", origin),
+    }
+}
@@ -0,0 +1,63 @@
+// Rebuild the tracked package list from the local database and the folder
+use crate::provenance::{self, Source};
+use crate::{
+    GREEN_CHECK, RED_CROSS, YELLOW_WARNING, ask_confirmation, database, get_system, load_config,
+    save_systemfile,
+};
+use std::process::exit;
+
+pub fn repair() {
+    let (_installed, declared, _existing) = get_system().unwrap_or_else(|e| {
+        eprintln!("{} Error reading packages :: {}", RED_CROSS, e);
+        exit(1);
+    });
+    let mut config = load_config();
+    let alpm = database::open();
+    let installed = |package: &str| database::satisfied(alpm.localdb(), package);
+
+    let mut repaired: Vec<String> = Vec::new();
+    let mut untracked: Vec<String> = Vec::new();
+    let mut corrections = Vec::new();
+
+    for package in &config.packages {
+        if repaired.contains(package) {
+            corrections.push(format!("- {} (duplicate entry)", package));
+        } else if !installed(package) {
+            corrections.push(format!("- {} (tracked but not installed)", package));
+        } else {
+            repaired.push(package.clone());
+        }
+    }
+
+    // Tracked packages that are no longer declared stay, the next install
+    // still has to remove them
+    for package in &declared {
+        if installed(package) && !repaired.contains(package) {
+            corrections.push(format!("+ {} (declared and installed but not tracked)", package));
+            repaired.push(package.clone());
+            untracked.push(package.clone());
+        }
+    }
+
+    if corrections.is_empty() {
+        println!("{} system.yaml matches the system", GREEN_CHECK);
+        return;
+    }
+
+    println!("{} Corrections to system.yaml :", YELLOW_WARNING);
+    for correction in &corrections {
+        println!("  {}", correction);
+    }
+
+    if !ask_confirmation("Do you want to write the repaired package list [Y/n] : ") {
+        return;
+    }
+
+    config.packages = repaired;
+    provenance::prune(&mut config);
+    provenance::track(&mut config, &untracked, Source::Folder);
+    match save_systemfile(&config) {
+        Ok(_) => println!("{} Repaired {} entries", GREEN_CHECK, corrections.len()),
+        Err(e) => eprintln!("{} Failed to save systemfile: {}", RED_CROSS, e),
+    }
+}
@@ -0,0 +1,268 @@
+// Third-party repositories declared in the packages folder, converged into pacman.conf
+use crate::declarations::FolderDeclarations;
+use crate::keyring::{self, Key};
+use crate::pacman_conf::PacmanConf;
+use crate::{
+    BLUE_GEAR, Config, GREEN_CHECK, YELLOW_WARNING, ask_confirmation, database, is_offline,
+    run_command,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Repository {
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub server: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub include: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sig_level: Option<String>,
+    // Full fingerprints of the signing keys to import and locally sign before
+    // the repository is used
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub keys: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub keyserver: Option<String>,
+    // Package files or URLs installed with `pacman -U` before the repository
+    // is enabled, e.g. its keyring and mirrorlist
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub bootstrap: Vec<String>,
+}
+
+impl Repository {
+    // Lines of its pacman.conf section, without the header
+    fn section(&self) -> Vec<String> {
+        let mut lines = Vec::new();
+        if let Some(sig_level) = &self.sig_level {
+            lines.push(format!("SigLevel = {}", sig_level));
+        }
+        for server in &self.server {
+            lines.push(format!("Server = {}", server));
+        }
+        if let Some(include) = &self.include {
+            lines.push(format!("Include = {}", include));
+        }
+        lines
+    }
+}
+
+// What init used to set up unconditionally, offered once by init to folders
+// that declare no repositories of their own
+fn builtin() -> Vec<Repository> {
+    vec![
+        Repository {
+            name: "multilib".to_string(),
+            server: Vec::new(),
+            include: Some("/etc/pacman.d/mirrorlist".to_string()),
+            sig_level: None,
+            keys: Vec::new(),
+            keyserver: None,
+            bootstrap: Vec::new(),
+        },
+        Repository {
+            name: "chaotic-aur".to_string(),
+            server: Vec::new(),
+            include: Some("/etc/pacman.d/chaotic-mirrorlist".to_string()),
+            sig_level: None,
+            keys: vec!["EF925EA60F33D0CB85C44AD13056513887B78AEB".to_string()],
+            keyserver: Some("keyserver.ubuntu.com".to_string()),
+            bootstrap: vec![
+                "https://cdn-mirror.chaotic.cx/chaotic-aur/chaotic-keyring.pkg.tar.zst".to_string(),
+                "https://cdn-mirror.chaotic.cx/chaotic-aur/chaotic-mirrorlist.pkg.tar.zst".to_string(),
+            ],
+        },
+    ]
+}
+
+// Repositories the folder itself declares, the only ones removed again once
+// undeclared. Those init set up from the built-in list are never removed.
+pub fn owned(declared: &FolderDeclarations) -> Vec<String> {
+    declared
+        .repositories
+        .iter()
+        .map(|repository| repository.name.clone())
+        .chain(declared.local_repository.iter().map(|local| local.name.clone()))
+        .collect()
+}
+
+pub fn declared(declared: &FolderDeclarations) -> Vec<Repository> {
+    let mut repositories = declared.repositories.clone();
+    // Registered once it has a database, pacman cannot sync it before
+    if let Some(local) = &declared.local_repository
+        && local.database().exists()
+    {
+        repositories.push(local.repository());
+    }
+    repositories
+}
+
+// Built-in repositories for the first init of a folder without any, later
+// runs only converge what the folder declares
+pub fn initial(declared: &FolderDeclarations, config: &Config) -> Vec<Repository> {
+    if declared.repositories.is_empty() && !config.builtin_repositories_offered {
+        builtin()
+    } else {
+        Vec::new()
+    }
+}
+
+fn import_keys(repository: &Repository) {
+    let keys: Vec<Key> = repository
+        .keys
+        .iter()
+        .map(|key| Key::from_fingerprint(key, repository.keyserver.clone()))
+        .collect();
+    keyring::ensure(&keys);
+}
+
+fn is_remote(package: &str) -> bool {
+    package.contains("://") && !package.starts_with("file://")
+}
+
+// Packages the bootstrap entries installed on the host
+fn install_bootstrap(repository: &Repository) -> Vec<String> {
+    let packages: Vec<String> = repository
+        .bootstrap
+        .iter()
+        .filter(|package| {
+            let skipped = is_offline() && is_remote(package);
+            if skipped {
+                eprintln!("{} Offline, not downloading {}", YELLOW_WARNING, package);
+            }
+            !skipped
+        })
+        .map(|package| format!("'{}'", package))
+        .collect();
+    if packages.is_empty() {
+        return Vec::new();
+    }
+    let before = database::host_packages();
+    run_command(&format!("pacman -U --noconfirm {}", packages.join(" ")), true);
+    database::host_packages()
+        .into_iter()
+        .filter(|package| !before.contains(package))
+        .collect()
+}
+
+// Repository that needs its section written, and whether it is new
+pub struct Change {
+    repository: Repository,
+    new: bool,
+}
+
+impl Change {
+    // Name of a repository whose section this run adds
+    pub fn added(&self) -> Option<&str> {
+        self.new.then_some(self.repository.name.as_str())
+    }
+}
+
+// Repositories whose section already reads exactly as declared
+pub fn matching(conf: &PacmanConf, repositories: &[Repository]) -> Vec<String> {
+    repositories
+        .iter()
+        .filter(|repository| {
+            conf.section(&repository.name).map(|section| section.settings())
+                == Some(repository.section())
+        })
+        .map(|repository| repository.name.clone())
+        .collect()
+}
+
+// Add or rewrite the section of every declared repository
+pub fn apply(conf: &mut PacmanConf, repositories: &[Repository]) -> Vec<Change> {
+    let mut changed = Vec::new();
+    for repository in repositories {
+        let existing = conf.section(&repository.name).map(|section| section.settings());
+        if existing.as_ref() == Some(&repository.section()) {
+            continue;
+        }
+        conf.set_section(&repository.name, &repository.section());
+        changed.push(Change {
+            repository: repository.clone(),
+            new: existing.is_none(),
+        });
+    }
+    changed
+}
+
+// Keys and bootstrap packages have to be in place before pacman uses the
+// repositories, the latter only for those that were not configured yet.
+// Returns the bootstrap packages installed per repository.
+pub fn prepare(changed: &[Change]) -> BTreeMap<String, Vec<String>> {
+    let mut bootstrapped = BTreeMap::new();
+    for change in changed {
+        println!("{} Configuring repository {}", BLUE_GEAR, change.repository.name);
+        import_keys(&change.repository);
+        if change.new {
+            let packages = install_bootstrap(&change.repository);
+            if !packages.is_empty() {
+                bootstrapped.insert(change.repository.name.clone(), packages);
+            }
+        }
+    }
+    bootstrapped
+}
+
+// Installed packages per repository, matched on the exact build it ships
+pub fn installed_packages(names: &[String]) -> BTreeMap<String, Vec<String>> {
+    if names.is_empty() {
+        return BTreeMap::new();
+    }
+    let alpm = database::open_with_syncdbs();
+    let mut installed: BTreeMap<String, Vec<String>> =
+        names.iter().map(|name| (name.clone(), Vec::new())).collect();
+    for pkg in alpm.localdb().pkgs() {
+        if let Some(repository) = database::repository_of(&alpm, pkg)
+            && let Some(packages) = installed.get_mut(&repository)
+        {
+            packages.push(pkg.name().to_string());
+        }
+    }
+    installed
+}
+
+// Point at what is left of removed repositories, offering to drop the
+// packages they were bootstrapped with, as recorded when they were configured
+pub fn report_removed(
+    installed: &BTreeMap<String, Vec<String>>,
+    bootstrapped: &BTreeMap<String, Vec<String>>,
+) {
+    for (repository, packages) in installed {
+        println!("{} Repository {} removed", GREEN_CHECK, repository);
+        // Offered for removal below
+        let bootstrap = bootstrapped.get(repository);
+        let packages: Vec<&String> = packages
+            .iter()
+            .filter(|package| !bootstrap.is_some_and(|bootstrap| bootstrap.contains(package)))
+            .collect();
+        if packages.is_empty() {
+            continue;
+        }
+        println!(
+            "{} Installed from {}, pin them to another repository or remove them :",
+            YELLOW_WARNING, repository
+        );
+        for package in packages {
+            println!("  {}", package);
+        }
+    }
+
+    let host = database::host_packages();
+    let bootstrap: Vec<String> = bootstrapped
+        .values()
+        .flatten()
+        .filter(|package| host.contains(package))
+        .cloned()
+        .collect();
+    if bootstrap.is_empty() {
+        return;
+    }
+    println!("Bootstrap packages of the removed repositories :\n{:?}", &bootstrap);
+    if !ask_confirmation("Do you want to remove them too [Y/n] : ") {
+        return;
+    }
+    // Installed on the host, like the bootstrap itself
+    run_command(&format!("pacman -Rns --noconfirm -- {}", bootstrap.join(" ")), true);
+}
@@ -0,0 +1,148 @@
+// Return the installed package set to a recorded generation
+use crate::generations::{self, InstalledPackage};
+use crate::journal::{self, Operation};
+use crate::provenance::{self, Source};
+use crate::{
+    BLUE_GEAR, GREEN_CHECK, RED_CROSS, YELLOW_WARNING, ask_confirmation, cache, history,
+    load_config, paths, run_command, save_systemfile, timestamp,
+};
+use std::process::exit;
+
+fn default_target() -> Option<u32> {
+    let numbers = generations::numbers();
+    numbers.len().checked_sub(2).map(|index| numbers[index])
+}
+
+// Reinstall the recorded builds, dependencies with --asdeps so they keep
+// their install reason, like install_locked
+fn restore(to_restore: &[(&String, &InstalledPackage)], declared: &[String]) {
+    let mirror = load_config().archive_mirror().to_string();
+    let source = |name: &str, wanted: &InstalledPackage| {
+        let source = cache::package_source(&mirror, name, &wanted.version, &wanted.arch);
+        if cache::find_cached(name, &wanted.version, &wanted.arch).is_none() {
+            println!("{} {} {} not cached, using {}", YELLOW_WARNING, name, wanted.version, source);
+        }
+        format!("'{}'", source)
+    };
+    let sources: Vec<String> = to_restore
+        .iter()
+        .map(|(name, wanted)| source(name, wanted))
+        .collect();
+    let dependencies: Vec<&str> = to_restore
+        .iter()
+        .filter(|(_, wanted)| wanted.dependency)
+        .map(|(name, _)| name.as_str())
+        .collect();
+    let explicit: Vec<&str> = to_restore
+        .iter()
+        .filter(|(_, wanted)| !wanted.dependency)
+        .map(|(name, _)| name.as_str())
+        .collect();
+
+    let restored: Vec<String> = to_restore.iter().map(|(name, _)| name.to_string()).collect();
+    let restored_declared: Vec<String> = restored
+        .iter()
+        .filter(|name| declared.contains(*name))
+        .cloned()
+        .collect();
+    journal::begin(Operation::Install, &restored_declared, Some(Source::Folder));
+    let started = timestamp::now();
+
+    // One transaction so packages that depend on each other are swapped
+    // together, install reasons are set afterwards
+    if !run_command(
+        &format!("pacman{} -U --noconfirm -- {}", paths::root_args(), sources.join(" ")),
+        true,
+    ) {
+        return;
+    }
+    for (reason, names) in [("--asdeps", &dependencies), ("--asexplicit", &explicit)] {
+        if !names.is_empty() {
+            run_command(
+                &format!("pacman{} -D {} -- {}", paths::root_args(), reason, names.join(" ")),
+                true,
+            );
+        }
+    }
+    history::record("restore", "rollback", started);
+
+    let mut config = load_config();
+    provenance::track(&mut config, &restored_declared, Source::Folder);
+    match save_systemfile(&config) {
+        Ok(_) => journal::complete(),
+        Err(e) => eprintln!("{} Failed to save systemfile: {}", RED_CROSS, e),
+    }
+}
+
+pub fn rollback(target: Option<u32>) {
+    let Some(number) = target.or_else(default_target) else {
+        eprintln!("{} No earlier generation to roll back to", RED_CROSS);
+        exit(1);
+    };
+    let generation = generations::load(number).unwrap_or_else(|e| {
+        eprintln!("{} Failed to read generation {}: {}", RED_CROSS, number, e);
+        exit(1);
+    });
+
+    let current = generations::installed_versions();
+    let to_restore: Vec<(&String, &InstalledPackage)> = generation
+        .installed
+        .iter()
+        .filter(|(name, wanted)| !current.get(*name).is_some_and(|pkg| pkg.same_build(wanted)))
+        .collect();
+    let to_remove: Vec<String> = current
+        .keys()
+        .filter(|name| !generation.installed.contains_key(*name))
+        .cloned()
+        .collect();
+
+    println!("{} Rolling back to generation {}", BLUE_GEAR, number);
+    if !to_restore.is_empty() {
+        println!("Packages to restore :");
+        for (name, wanted) in &to_restore {
+            let installed = current.get(*name).map_or("not installed", |pkg| pkg.version.as_str());
+            println!("  {} {} -> {}", name, installed, wanted.version);
+        }
+    }
+    if !to_remove.is_empty() {
+        println!("Packages to remove :\n{:?}", &to_remove);
+    }
+
+    if (!to_restore.is_empty() || !to_remove.is_empty())
+        && !ask_confirmation("Do you want to proceed with the rollback [Y/n] : ")
+    {
+        return;
+    }
+
+    if !to_restore.is_empty() {
+        restore(&to_restore, &generation.declared);
+    }
+
+    // Removed after restoring so that older versions no longer depend on them
+    if !to_remove.is_empty() {
+        journal::begin(Operation::Remove, &to_remove, None);
+        let started = timestamp::now();
+        if run_command(
+            &format!("pacman{} -Rn --noconfirm -- {}", paths::root_args(), to_remove.join(" ")),
+            true,
+        ) {
+            history::record("remove", "rollback", started);
+            let mut config = load_config();
+            provenance::untrack(&mut config, &to_remove);
+            match save_systemfile(&config) {
+                Ok(_) => journal::complete(),
+                Err(e) => eprintln!("{} Failed to save systemfile: {}", RED_CROSS, e),
+            }
+        }
+    }
+
+    let mut config = load_config();
+    config.packages = generation.declared;
+    provenance::prune(&mut config);
+    if let Err(e) = save_systemfile(&config) {
+        eprintln!("{} Failed to save systemfile: {}", RED_CROSS, e);
+        return;
+    }
+
+    println!("{} Rolled back to generation {}", GREEN_CHECK, number);
+}
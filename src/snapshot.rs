@@ -0,0 +1,296 @@
+// Filesystem snapshots taken before and after package transactions
+use crate::{GREEN_CHECK, RED_CROSS, YELLOW_WARNING, ask_confirmation, privilege, read_system_file, timestamp};
+use serde::{Deserialize, Serialize};
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::process::exit;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(tag = "backend", rename_all = "kebab-case")]
+pub enum SnapshotBackend {
+    #[default]
+    None,
+    Snapper {
+        #[serde(default = "default_snapper_config")]
+        config: String,
+    },
+    // Read-only snapshots of `subvolume` created inside `destination`
+    Btrfs {
+        subvolume: String,
+        destination: String,
+    },
+    Timeshift,
+    // Shell commands, `{label}` and `{phase}` are substituted
+    Command {
+        pre: String,
+        #[serde(default)]
+        post: Option<String>,
+    },
+    // Only appends the commands that would run to `file`, for testing
+    Record {
+        file: String,
+    },
+}
+
+fn default_snapper_config() -> String {
+    "root".to_string()
+}
+
+pub struct Snapshot {
+    label: String,
+    backend: SnapshotBackend,
+    // Snapper pairs post snapshots with the number of their pre snapshot
+    pre_number: Option<String>,
+}
+
+pub fn shell_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', r"'\''"))
+}
+
+fn run_privileged(command: &str) -> Option<String> {
+    let output = privilege::command("sh").arg("-c").arg(command).output();
+    match output {
+        Ok(output) if output.status.success() => {
+            Some(String::from_utf8_lossy(&output.stdout).trim().to_string())
+        }
+        Ok(output) => {
+            eprintln!("{}", String::from_utf8_lossy(&output.stderr).trim());
+            None
+        }
+        Err(e) => {
+            eprintln!("{} Failed to run {}: {}", RED_CROSS, command, e);
+            None
+        }
+    }
+}
+
+fn record(file: &str, line: &str) -> Option<String> {
+    let result = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(file)
+        .and_then(|mut file| writeln!(file, "{}", line));
+    match result {
+        Ok(_) => Some(String::new()),
+        Err(e) => {
+            eprintln!("{} Failed to write {}: {}", RED_CROSS, file, e);
+            None
+        }
+    }
+}
+
+fn pre_command(backend: &SnapshotBackend, label: &str) -> Option<String> {
+    let quoted = shell_quote(label);
+    match backend {
+        SnapshotBackend::None => None,
+        SnapshotBackend::Snapper { config } => Some(format!(
+            "snapper -c {} create --type pre --print-number --cleanup-algorithm number --description {}",
+            shell_quote(config),
+            quoted
+        )),
+        SnapshotBackend::Btrfs { subvolume, destination } => Some(format!(
+            "btrfs subvolume snapshot -r {} {}",
+            shell_quote(subvolume),
+            shell_quote(&format!("{}/novarch-{}-pre", destination, timestamp::now()))
+        )),
+        SnapshotBackend::Timeshift => Some(format!(
+            "timeshift --create --scripted --tags O --comments {}",
+            quoted
+        )),
+        SnapshotBackend::Command { pre, .. } => {
+            Some(pre.replace("{label}", &quoted).replace("{phase}", "pre"))
+        }
+        SnapshotBackend::Record { .. } => Some(format!("pre {}", quoted)),
+    }
+}
+
+fn post_command(snapshot: &Snapshot) -> Option<String> {
+    let quoted = shell_quote(&snapshot.label);
+    match &snapshot.backend {
+        SnapshotBackend::None | SnapshotBackend::Timeshift => None,
+        SnapshotBackend::Snapper { config } => snapshot.pre_number.as_ref().map(|number| {
+            format!(
+                "snapper -c {} create --type post --pre-number {} --cleanup-algorithm number --description {}",
+                shell_quote(config),
+                number,
+                quoted
+            )
+        }),
+        SnapshotBackend::Btrfs { subvolume, destination } => Some(format!(
+            "btrfs subvolume snapshot -r {} {}",
+            shell_quote(subvolume),
+            shell_quote(&format!("{}/novarch-{}-post", destination, timestamp::now()))
+        )),
+        SnapshotBackend::Command { post, .. } => post
+            .as_ref()
+            .map(|post| post.replace("{label}", &quoted).replace("{phase}", "post")),
+        SnapshotBackend::Record { .. } => Some(format!("post {}", quoted)),
+    }
+}
+
+fn execute(backend: &SnapshotBackend, command: &str) -> Option<String> {
+    match backend {
+        SnapshotBackend::Record { file } => record(file, command),
+        _ => run_privileged(command),
+    }
+}
+
+// Snapper prints the number of the pre snapshot, its post snapshot refers to it
+fn taken(label: &str, backend: SnapshotBackend, output: &str) -> Snapshot {
+    let pre_number = matches!(backend, SnapshotBackend::Snapper { .. })
+        .then(|| output.trim().to_string());
+    Snapshot {
+        label: label.to_string(),
+        backend,
+        pre_number,
+    }
+}
+
+pub fn pre(label: &str) -> Option<Snapshot> {
+    let backend = read_system_file()
+        .map(|config| config.snapshots)
+        .unwrap_or_default();
+    let command = pre_command(&backend, label)?;
+
+    match execute(&backend, &command) {
+        Some(output) => {
+            println!("{} Snapshot taken: {}", GREEN_CHECK, label);
+            Some(taken(label, backend, &output))
+        }
+        None => {
+            eprintln!("{} Failed to take snapshot before transaction", YELLOW_WARNING);
+            if !ask_confirmation("Continue without a snapshot [Y/n] : ") {
+                exit(1);
+            }
+            None
+        }
+    }
+}
+
+pub fn post(snapshot: Option<Snapshot>) {
+    let Some(snapshot) = snapshot else {
+        return;
+    };
+    if let Some(command) = post_command(&snapshot) {
+        match execute(&snapshot.backend, &command) {
+            Some(_) => println!("{} Post-transaction snapshot taken", GREEN_CHECK),
+            None => eprintln!("{} Failed to take post-transaction snapshot", YELLOW_WARNING),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::fs;
+
+    fn snapper() -> SnapshotBackend {
+        SnapshotBackend::Snapper {
+            config: "root".to_string(),
+        }
+    }
+
+    #[test]
+    fn shell_quote_wraps_and_escapes_single_quotes() {
+        assert_eq!(shell_quote("plain"), "'plain'");
+        assert_eq!(shell_quote("it's"), r"'it'\''s'");
+        assert_eq!(shell_quote("$(rm -rf /)"), "'$(rm -rf /)'");
+    }
+
+    #[test]
+    fn none_backend_runs_nothing() {
+        assert_eq!(pre_command(&SnapshotBackend::None, "label"), None);
+        assert!(post_command(&taken("label", SnapshotBackend::None, "")).is_none());
+    }
+
+    #[test]
+    fn snapper_commands() {
+        assert_eq!(
+            pre_command(&snapper(), "novarch install: vim").as_deref(),
+            Some(
+                "snapper -c 'root' create --type pre --print-number --cleanup-algorithm number \
+                 --description 'novarch install: vim'"
+            )
+        );
+        assert_eq!(
+            post_command(&taken("novarch install: vim", snapper(), "42\n")).as_deref(),
+            Some(
+                "snapper -c 'root' create --type post --pre-number 42 --cleanup-algorithm number \
+                 --description 'novarch install: vim'"
+            )
+        );
+    }
+
+    #[test]
+    fn snapper_post_pairs_with_its_pre_number() {
+        let first = taken("first", snapper(), "7");
+        let second = taken("second", snapper(), " 8 ");
+        assert!(post_command(&first).unwrap().contains("--pre-number 7 "));
+        assert!(post_command(&second).unwrap().contains("--pre-number 8 "));
+    }
+
+    #[test]
+    fn only_snapper_keeps_a_pre_number() {
+        let snapshot = taken("label", SnapshotBackend::Timeshift, "output");
+        assert_eq!(snapshot.pre_number, None);
+        assert!(post_command(&snapshot).is_none());
+    }
+
+    #[test]
+    fn btrfs_commands() {
+        let backend = SnapshotBackend::Btrfs {
+            subvolume: "/".to_string(),
+            destination: "/.snapshots".to_string(),
+        };
+        let pre = pre_command(&backend, "label").unwrap();
+        assert!(pre.starts_with("btrfs subvolume snapshot -r '/' '/.snapshots/novarch-"));
+        assert!(pre.ends_with("-pre'"));
+        let post = post_command(&taken("label", backend, "")).unwrap();
+        assert!(post.ends_with("-post'"));
+    }
+
+    #[test]
+    fn timeshift_command() {
+        assert_eq!(
+            pre_command(&SnapshotBackend::Timeshift, "label").as_deref(),
+            Some("timeshift --create --scripted --tags O --comments 'label'")
+        );
+    }
+
+    #[test]
+    fn command_substitutes_label_and_phase() {
+        let backend = SnapshotBackend::Command {
+            pre: "snap {phase} {label}".to_string(),
+            post: Some("snap {phase} {label}".to_string()),
+        };
+        assert_eq!(pre_command(&backend, "it's").as_deref(), Some(r"snap pre 'it'\''s'"));
+        assert_eq!(
+            post_command(&taken("it's", backend, "")).as_deref(),
+            Some(r"snap post 'it'\''s'")
+        );
+
+        let without_post = SnapshotBackend::Command {
+            pre: "snap".to_string(),
+            post: None,
+        };
+        assert!(post_command(&taken("label", without_post, "")).is_none());
+    }
+
+    #[test]
+    fn record_appends_commands_to_its_file() {
+        let file = env::temp_dir().join(format!("novarch-record-{}", std::process::id()));
+        let file = file.to_string_lossy().to_string();
+        let _ = fs::remove_file(&file);
+        let backend = SnapshotBackend::Record { file: file.clone() };
+
+        let pre = pre_command(&backend, "label").unwrap();
+        assert_eq!(execute(&backend, &pre).as_deref(), Some(""));
+        let snapshot = taken("label", backend, "");
+        let post = post_command(&snapshot).unwrap();
+        assert!(execute(&snapshot.backend, &post).is_some());
+
+        assert_eq!(fs::read_to_string(&file).unwrap(), "pre 'label'\npost 'label'\n");
+        fs::remove_file(&file).unwrap();
+    }
+}
@@ -0,0 +1,146 @@
+// Unix timestamps and their human readable UTC form
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or(0)
+}
+
+// Days since 1970-01-01 to a (year, month, day) civil date
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+pub fn format(timestamp: u64) -> String {
+    let seconds = timestamp as i64;
+    let (year, month, day) = civil_from_days(seconds.div_euclid(86_400));
+    let time = seconds.rem_euclid(86_400);
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02} UTC",
+        year,
+        month,
+        day,
+        time / 3600,
+        time % 3600 / 60,
+        time % 60
+    )
+}
+
+// Inverse of civil_from_days
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let yoe = year.rem_euclid(400);
+    let month = i64::from(month);
+    let doy = (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + i64::from(day) - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
+
+fn parse_date_parts(date: &str) -> Option<(i64, u32, u32)> {
+    let mut parts = date.splitn(3, '-');
+    let year = parts.next()?.parse().ok()?;
+    let month = parts.next()?.parse().ok()?;
+    let day = parts.next()?.parse().ok()?;
+    ((1..=12).contains(&month) && (1..=31).contains(&day)).then_some((year, month, day))
+}
+
+// Midnight UTC of a YYYY-MM-DD date
+pub fn parse_date(date: &str) -> Option<u64> {
+    let (year, month, day) = parse_date_parts(date.trim())?;
+    u64::try_from(days_from_civil(year, month, day) * 86_400).ok()
+}
+
+// pacman.log timestamps, either `2024-05-01T10:00:00+0200` or the older
+// `2019-01-01 10:00` which carries no offset and is read as UTC
+pub fn parse_pacman(value: &str) -> Option<u64> {
+    let (date, rest) = value.split_once(['T', ' '])?;
+    let (year, month, day) = parse_date_parts(date)?;
+
+    let (time, offset) = match rest.find(['+', '-']) {
+        Some(index) => (&rest[..index], &rest[index..]),
+        None => (rest, ""),
+    };
+    let mut fields = time.split(':').map(|field| field.parse::<i64>().ok());
+    let hours = fields.next()??;
+    let minutes = fields.next()??;
+    let seconds = fields.next().flatten().unwrap_or(0);
+
+    let offset_seconds = if offset.len() == 5 {
+        let sign = if offset.starts_with('-') { -1 } else { 1 };
+        let hours: i64 = offset[1..3].parse().ok()?;
+        let minutes: i64 = offset[3..5].parse().ok()?;
+        sign * (hours * 3600 + minutes * 60)
+    } else {
+        0
+    };
+
+    let local = days_from_civil(year, month, day) * 86_400 + hours * 3600 + minutes * 60 + seconds;
+    u64::try_from(local - offset_seconds).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn days_from_civil_inverts_civil_from_days() {
+        assert_eq!(days_from_civil(1970, 1, 1), 0);
+        assert_eq!(days_from_civil(2000, 3, 1), 11_017);
+        assert_eq!(days_from_civil(1969, 12, 31), -1);
+        for days in [-1, 0, 59, 365, 11_016, 19_844, 25_000] {
+            let (year, month, day) = civil_from_days(days);
+            assert_eq!(days_from_civil(year, month, day), days);
+        }
+    }
+
+    #[test]
+    fn leap_day_is_counted() {
+        assert_eq!(parse_date("2024-03-01").unwrap() - parse_date("2024-02-28").unwrap(), 2 * 86_400);
+        assert_eq!(parse_date("2023-03-01").unwrap() - parse_date("2023-02-28").unwrap(), 86_400);
+    }
+
+    #[test]
+    fn parse_date_rejects_invalid_dates() {
+        assert_eq!(parse_date("2024-05-01"), Some(1_714_521_600));
+        assert_eq!(parse_date(" 2024-05-01\n"), Some(1_714_521_600));
+        assert_eq!(parse_date("2024-13-01"), None);
+        assert_eq!(parse_date("2024-05-00"), None);
+        assert_eq!(parse_date("2024-05"), None);
+        assert_eq!(parse_date("yesterday"), None);
+    }
+
+    #[test]
+    fn parse_pacman_reads_offset_timestamps() {
+        assert_eq!(parse_pacman("2024-05-01T10:00:00+0000"), Some(1_714_557_600));
+        assert_eq!(parse_pacman("2024-05-01T12:00:00+0200"), Some(1_714_557_600));
+        assert_eq!(parse_pacman("2024-05-01T05:30:00-0430"), Some(1_714_557_600));
+        // A negative offset can move the UTC time into the next day
+        assert_eq!(parse_pacman("2024-04-30T22:00:00-0500"), Some(1_714_532_400));
+        assert_eq!(format(1_714_532_400), "2024-05-01 03:00:00 UTC");
+    }
+
+    #[test]
+    fn parse_pacman_reads_old_timestamps_as_utc() {
+        assert_eq!(parse_pacman("2019-01-01 10:00"), Some(1_546_336_800));
+        assert_eq!(format(1_546_336_800), "2019-01-01 10:00:00 UTC");
+    }
+
+    #[test]
+    fn parse_pacman_rejects_garbage() {
+        assert_eq!(parse_pacman("2024-05-01"), None);
+        assert_eq!(parse_pacman("2024-05-01Tnoon"), None);
+        assert_eq!(parse_pacman("1969-12-31T23:00:00+0000"), None);
+    }
+}